@@ -1,5 +1,22 @@
+use std::io::{stdin, stdout, Write};
+
+use crate::options::Options;
+
 pub trait FuseFileSystem {
-    fn new(hostname: &str, port: u16, secured: bool, debug: bool) -> Self;
+    fn new(hostname: &str, port: u16, secured: bool, debug: bool, options: Options) -> Self;
     fn mount(&mut self, mount_point: &str, username: &str, password: &str);
     fn unmount(&self);
-}
\ No newline at end of file
+}
+
+/// Asks for the code of the authenticator app on the terminal.  Returns `None` when nothing was entered.
+pub fn read_otp_code() -> Option<String> {
+    let mut stdout = stdout();
+    stdout.write_all(b"2-step verification code: ").ok()?;
+    stdout.flush().ok()?;
+
+    let mut line = String::new();
+    match stdin().read_line(&mut line) {
+        Ok(_) if !line.trim().is_empty() => Some(line.trim().to_string()),
+        _ => None
+    }
+}
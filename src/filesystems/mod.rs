@@ -8,4 +8,6 @@ pub mod fuse_fileystem;
 #[cfg(target_family = "windows")]
 pub mod windows_fuse_filesystem;
 #[cfg(target_family = "unix")]
-pub mod unix_fuse_filesystem;
\ No newline at end of file
+pub mod unix_fuse_filesystem;
+#[cfg(target_family = "unix")]
+mod owner_map;
\ No newline at end of file
@@ -0,0 +1,56 @@
+use std::{collections::HashMap, fs, io::{Error, ErrorKind}, path::Path};
+
+/// Maps the users and groups of the NAS to local ids.
+///
+/// Each line of the file reads `user <name> <uid>` or `group <name> <gid>`.  Empty lines
+/// and lines starting with `#` are ignored.
+#[derive(Default)]
+pub struct OwnerMap {
+    users: HashMap<String, u32>,
+    groups: HashMap<String, u32>,
+}
+
+impl OwnerMap {
+    pub fn load(path: &Path) -> Result<OwnerMap, Error> {
+        let content = fs::read_to_string(path)?;
+        let mut owner_map = OwnerMap::default();
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (kind, name, id) = match fields.as_slice() {
+                [kind, name, id] => (*kind, *name, *id),
+                _ => return Err(Self::invalid_line(path, index, line))
+            };
+
+            let id = match id.parse::<u32>() {
+                Ok(id) => id,
+                Err(_) => return Err(Self::invalid_line(path, index, line))
+            };
+
+            match kind {
+                "user" => owner_map.users.insert(name.to_string(), id),
+                "group" => owner_map.groups.insert(name.to_string(), id),
+                _ => return Err(Self::invalid_line(path, index, line))
+            };
+        }
+
+        Ok(owner_map)
+    }
+
+    pub fn get_uid(&self, user: &str) -> Option<u32> {
+        self.users.get(user).copied()
+    }
+
+    pub fn get_gid(&self, group: &str) -> Option<u32> {
+        self.groups.get(group).copied()
+    }
+
+    fn invalid_line(path: &Path, index: usize, line: &str) -> Error {
+        Error::new(ErrorKind::InvalidData, format!("{}:{}: expected 'user|group <name> <id>', got '{}'", path.display(), index + 1, line))
+    }
+}
@@ -1,140 +1,760 @@
 use crate::filesystems::FuseFileSystem;
+use crate::filesystems::fuse_fileystem::read_otp_code;
+use crate::filesystems::owner_map::OwnerMap;
+use crate::options::{Options, PermissionMode};
 use crate::synology_api::{FileStationFileSystem, FileSystemInfo};
 
-use std::{ffi::OsStr, time::Duration, collections::HashMap};
-use fuser::{FileType, FileAttr, Filesystem, MountOption};
-use libc::{ENOSYS, ENOENT, EEXIST};
-use log::error;
+use std::{ffi::OsStr, path::Path, time::{Duration, SystemTime}, collections::{HashMap, HashSet, hash_map::Entry}, sync::{Arc, Mutex, Weak}, thread};
+use fuser::{FileType, FileAttr, Filesystem, KernelConfig, MountOption, Notifier, Session, fuse_forget_one};
+use fuser::consts::{FUSE_AUTO_INVAL_DATA, FUSE_DO_READDIRPLUS, FUSE_READDIRPLUS_AUTO};
+use libc::{c_int, ENOSYS, ENOENT, EBADF, EEXIST, EXDEV, EACCES, EINVAL, ENODATA, ERANGE, F_OK, R_OK, W_OK, X_OK, O_ACCMODE, O_RDONLY};
+use log::{debug, error, info};
+
+/// Name used to ask whether new entries may be created in a folder.
+const ACCESS_PROBE_NAME: &str = ".synology-filestation-fuse-access";
+
+/// Number of entries requested from the NAS at a time when listing a folder.
+const DIRECTORY_PAGE_SIZE: u64 = 1000;
+
+/// Namespace of the extended attributes exposing the metadata FileStation keeps about each entry.
+const XATTR_PREFIX: &str = "user.synology.";
+
+/// An open folder.  Pages of the listing are kept for as long as the folder is open,
+/// so the offsets handed to the kernel stay valid between readdir calls.
+struct DirectoryHandle {
+    path: String,
+    info: FileSystemInfo,
+    parent_ino: u64,
+    pages: HashMap<u64, Vec<FileSystemInfo>>,
+    total: Option<u64>,
+}
+
+/// Open files and folders, by handle.
+struct Handles {
+    next_handle: u64,
+    files: HashMap<u64, FileSystemInfo>,
+    /// Locked on their own, so listing one folder does not hold up opening another.
+    directories: HashMap<u64, Arc<Mutex<DirectoryHandle>>>,
+}
+
+impl Handles {
+    fn allocate(&mut self) -> u64 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+
+        handle
+    }
+}
 
 struct UnixFileSystemHandler {
-    filestation_filesystem: FileStationFileSystem,
+    state: Arc<HandlerState>,
+}
+
+/// Everything requests need that is not tied to an open handle, shared with the threads serving them.
+struct HandlerState {
+    filestation_filesystem: Arc<FileStationFileSystem>,
     block_size: u32,
-    next_handle: u64,
-    file_handles: HashMap<u64, FileSystemInfo>
+    uid: u32,
+    gid: u32,
+    owner_map: OwnerMap,
+    umask: u16,
+    permissions: PermissionMode,
+    attr_ttl: Duration,
+    entry_ttl: Duration,
+    negative_ttl: Duration,
+    /// MD5 sums computed by the NAS, with the mtime of the file they belong to.
+    md5_sums: Mutex<HashMap<u64, (SystemTime, String)>>,
+    handles: Mutex<Handles>,
 }
 
 impl UnixFileSystemHandler {
-    pub fn new(filestation_filesystem: FileStationFileSystem) -> UnixFileSystemHandler {
+    pub fn new(filestation_filesystem: Arc<FileStationFileSystem>, options: &Options) -> UnixFileSystemHandler {
+        // Files belong to whoever mounted the share unless told otherwise.
+        let (default_uid, default_gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+        let owner_map = match &options.owner_map {
+            Some(path) => match OwnerMap::load(path) {
+                Ok(owner_map) => owner_map,
+                Err(error) => {
+                    error!("Ignoring the owner map {}: {}", path.display(), error);
+
+                    OwnerMap::default()
+                }
+            },
+            None => OwnerMap::default()
+        };
+
         UnixFileSystemHandler {
-            filestation_filesystem,
-            block_size: 4096,
-            next_handle: 1,
-            file_handles: HashMap::new()
+            state: Arc::new(HandlerState {
+                filestation_filesystem,
+                block_size: 4096,
+                uid: options.uid.unwrap_or(default_uid),
+                gid: options.gid.unwrap_or(default_gid),
+                owner_map,
+                umask: options.umask.unwrap_or(0),
+                permissions: options.permissions,
+                attr_ttl: options.attr_ttl,
+                entry_ttl: options.entry_ttl,
+                negative_ttl: options.negative_ttl,
+                md5_sums: Mutex::new(HashMap::new()),
+                handles: Mutex::new(Handles {
+                    next_handle: 1,
+                    files: HashMap::new(),
+                    directories: HashMap::new(),
+                }),
+            }),
+        }
+    }
+
+    /// Serves a request on a thread of its own, so a slow call to the NAS does not hold up the others.
+    fn spawn(&self, work: impl FnOnce(&HandlerState) + Send + 'static) {
+        let state = self.state.clone();
+        self.state.filestation_filesystem.spawn(move || work(&state));
+    }
+
+    fn split_path(path: &str) -> (&str, &str) {
+        match path.rfind('/') {
+            Some(0) => ("/", &path[1..]),
+            Some(index) => (&path[..index], &path[index + 1..]),
+            None => ("/", path)
+        }
+    }
+
+    fn negative_attr() -> FileAttr {
+        FileAttr {
+            ino: 0,
+            size: 0,
+            blocks: 0,
+            atime: SystemTime::UNIX_EPOCH,
+            mtime: SystemTime::UNIX_EPOCH,
+            ctime: SystemTime::UNIX_EPOCH,
+            crtime: SystemTime::UNIX_EPOCH,
+            kind: FileType::RegularFile,
+            perm: 0,
+            nlink: 0,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 0,
+            flags: 0,
+        }
+    }
+
+    fn file_type(info: &FileSystemInfo) -> FileType {
+        if info.link_target.is_some() {
+            FileType::Symlink
+        } else if info.is_dir {
+            FileType::Directory
+        } else {
+            FileType::RegularFile
+        }
+    }
+
+    /// The target of a link relative to the folder holding it, so it resolves wherever the shares are mounted.
+    fn relative_link_target(path: &str, target: &str) -> String {
+        let (folder, _name) = Self::split_path(path);
+        let folder_parts: Vec<&str> = folder.split('/').filter(|part| !part.is_empty()).collect();
+        let target_parts: Vec<&str> = target.split('/').filter(|part| !part.is_empty()).collect();
+
+        let common = folder_parts
+            .iter()
+            .zip(target_parts.iter())
+            .take_while(|(folder_part, target_part)| folder_part == target_part)
+            .count();
+
+        let mut parts: Vec<&str> = vec![".."; folder_parts.len() - common];
+        parts.extend(&target_parts[common..]);
+
+        if parts.is_empty() {
+            ".".to_string()
+        } else {
+            parts.join("/")
+        }
+    }
+
+    /// Whether `uid` and `gid` may access a file with `attr` as `mask` asks, going by the owner, group
+    /// or other bits as the kernel would for a local file.  Supplementary groups are not known here.
+    fn is_permitted(attr: &FileAttr, uid: u32, gid: u32, mask: i32) -> bool {
+        if uid == 0 {
+            // Root may read and write anything, and run anything someone may run.
+            return mask & X_OK == 0 || attr.perm & 0o111 != 0;
+        }
+
+        let shift = if uid == attr.uid {
+            6
+        } else if gid == attr.gid {
+            3
+        } else {
+            0
+        };
+        let bits = (attr.perm >> shift) & 0o7;
+
+        (mask & R_OK == 0 || bits & 0o4 != 0)
+            && (mask & W_OK == 0 || bits & 0o2 != 0)
+            && (mask & X_OK == 0 || bits & 0o1 != 0)
+    }
+
+    /// Names of the extended attributes listed for an entry, without the namespace.
+    fn xattr_names(info: &FileSystemInfo) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if info.owner.is_some() {
+            names.push("owner");
+            names.push("group");
+        }
+
+        names.push("acl");
+
+        if info.real_path.is_some() {
+            names.push("real_path");
+        }
+        // Only entries within a share live on a volume.
+        if info.path != "/" && !FileStationFileSystem::is_search_path(&info.path) {
+            names.push("volume_status");
+        }
+
+        names
+    }
+
+    /// Answers with the size alone when the kernel asks for it, and with the data otherwise.
+    fn reply_xattr(reply: fuser::ReplyXattr, size: u32, data: &[u8]) {
+        if size == 0 {
+            reply.size(data.len() as u32);
+        } else if data.len() > size as usize {
+            reply.error(ERANGE);
+        } else {
+            reply.data(data);
+        }
+    }
+}
+
+impl HandlerState {
+    /// Returns the entry at `index` of an open folder, where 0 and 1 are "." and "..".
+    fn get_directory_entry(&self, handle: &mut DirectoryHandle, index: u64) -> Result<Option<(String, FileSystemInfo)>, i32> {
+        match index {
+            0 => return Ok(Some((".".to_string(), handle.info.clone()))),
+            1 => {
+                // The kernel takes nothing but the inode from "..", so the folder's own attributes will do.
+                let mut parent_info = handle.info.clone();
+                parent_info.ino = handle.parent_ino;
+
+                return Ok(Some(("..".to_string(), parent_info)));
+            },
+            _ => {}
+        }
+
+        let child_index = index - 2;
+        if let Some(total) = handle.total {
+            if child_index >= total {
+                return Ok(None);
+            }
+        }
+
+        let page_index = child_index / DIRECTORY_PAGE_SIZE;
+        if let Entry::Vacant(entry) = handle.pages.entry(page_index) {
+            match self.filestation_filesystem.list_files_page(&handle.path, page_index * DIRECTORY_PAGE_SIZE, DIRECTORY_PAGE_SIZE) {
+                Ok((files, total)) => {
+                    entry.insert(files);
+                    handle.total = Some(total);
+                },
+                Err(error) => return Err(error.to_errno())
+            }
+        }
+
+        let page = &handle.pages[&page_index];
+        match page.get((child_index % DIRECTORY_PAGE_SIZE) as usize) {
+            Some(file) => Ok(Some((file.name.clone(), file.clone()))),
+            None => Ok(None)
         }
     }
 
     fn size2blocks(&self, size: u64) -> u64 {
         (size + self.block_size as u64 - 1) / self.block_size as u64
     }
+
+    fn mode_bits(&self, info: &FileSystemInfo) -> u16 {
+        // With Windows ACLs the POSIX bits the NAS reports mean nothing.
+        let use_acl = match self.permissions {
+            PermissionMode::Auto => info.is_acl_mode,
+            PermissionMode::Posix => false,
+            PermissionMode::Acl => true,
+        };
+
+        if use_acl {
+            info.acl_perm()
+        } else {
+            info.perm
+        }
+    }
+
+    fn xattr_value(&self, info: &FileSystemInfo, name: &str) -> Result<Vec<u8>, i32> {
+        // The NAS has to read the whole file for the MD5, so it is only computed when asked for by name,
+        // and left out of the listing that tools such as `getfattr -d` read every attribute from.
+        let is_md5 = name == "md5" && !info.is_dir && info.link_target.is_none();
+        if !is_md5 && !UnixFileSystemHandler::xattr_names(info).contains(&name) {
+            return Err(ENODATA);
+        }
+
+        match name {
+            "owner" => Ok(info.owner.as_ref().map(|owner| owner.user.clone()).unwrap_or_default().into_bytes()),
+            "group" => Ok(info.owner.as_ref().map(|owner| owner.group.clone()).unwrap_or_default().into_bytes()),
+            "acl" => Ok(serde_json::to_vec(&info.acl).unwrap()),
+            "real_path" => Ok(info.real_path.clone().unwrap_or_default().into_bytes()),
+            "volume_status" => match self.filestation_filesystem.get_volume_status(&info.path) {
+                Ok(Some(volume_status)) => Ok(serde_json::to_vec(&volume_status).unwrap()),
+                Ok(None) => Err(ENODATA),
+                Err(error) => Err(error.to_errno())
+            },
+            "md5" => {
+                // getfattr asks for the size before the value, so keep the sum until the file changes.
+                if let Some((mtime, md5)) = self.md5_sums.lock().unwrap().get(&info.ino) {
+                    if *mtime == info.mtime {
+                        return Ok(md5.clone().into_bytes());
+                    }
+                }
+
+                match self.filestation_filesystem.get_md5(&info.path) {
+                    Ok(md5) => {
+                        self.md5_sums.lock().unwrap().insert(info.ino, (info.mtime, md5.clone()));
+
+                        Ok(md5.into_bytes())
+                    },
+                    Err(error) => Err(error.to_errno())
+                }
+            },
+            _ => Err(ENODATA)
+        }
+    }
+
+    fn file_attr(&self, info: &FileSystemInfo) -> FileAttr {
+        let kind = UnixFileSystemHandler::file_type(info);
+        let (size, nlink) = match kind {
+            FileType::Symlink => {
+                let target = UnixFileSystemHandler::relative_link_target(&info.path, info.link_target.as_deref().unwrap_or_default());
+                (target.len() as u64, 1)
+            },
+            // "." and the entry in the parent.  Subdirectories are not counted, as that would need a listing.
+            FileType::Directory => (info.size, 2),
+            _ => (info.size, 1)
+        };
+
+        // Owners the map does not know about fall back to the configured ids.
+        let uid = info.owner.as_ref().and_then(|owner| self.owner_map.get_uid(&owner.user)).unwrap_or(self.uid);
+        let gid = info.owner.as_ref().and_then(|owner| self.owner_map.get_gid(&owner.group)).unwrap_or(self.gid);
+
+        FileAttr {
+            ino: info.ino,
+            size,
+            blksize: self.block_size,
+            blocks: self.size2blocks(size),
+            atime: info.atime,
+            mtime: info.mtime,
+            ctime: info.ctime,
+            crtime: info.crtime,
+            kind,
+            perm: self.mode_bits(info) & 0o7777 & !self.umask,
+            nlink,
+            uid,
+            gid,
+            rdev: 0,
+            flags: 0,
+        }
+    }
 }
 
 impl Filesystem for UnixFileSystemHandler {
+    fn init(&mut self, _req: &fuser::Request<'_>, config: &mut KernelConfig) -> Result<(), c_int> {
+        // Let the kernel take the attributes from the listing, instead of looking up every entry.
+        if let Err(unsupported) = config.add_capabilities(FUSE_DO_READDIRPLUS | FUSE_READDIRPLUS_AUTO) {
+            error!("The kernel does not support readdirplus: {:#x}", unsupported);
+        }
+
+        // Drop cached pages whenever a file turns out to have a new mtime, e.g. after it changed on the NAS.
+        if let Err(unsupported) = config.add_capabilities(FUSE_AUTO_INVAL_DATA) {
+            error!("The kernel does not support automatic page cache invalidation: {:#x}", unsupported);
+        }
+
+        Ok(())
+    }
+
     fn destroy(&mut self) {
-        self.filestation_filesystem.logout().unwrap();
+        self.state.filestation_filesystem.logout().unwrap();
     }
 
     fn getattr(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyAttr) {
-        let path_result = self.filestation_filesystem.get_path_for_ino(ino);
-        if path_result.is_err() {
-            reply.error(ENOSYS);
-            return;
-        }
-        let path: String = path_result.unwrap();
-
-        println!("path: {}", path);
-
-        let info_result = self.filestation_filesystem.get_info(&path);
-        let ttl = Duration::from_secs(10);
-
-        match info_result {
-            Ok(info) => {
-                reply.attr(&ttl, &FileAttr {
-                    ino: info.ino,
-                    size: info.size,
-                    blksize: self.block_size,
-                    blocks: self.size2blocks(info.size),
-                    atime: info.atime,
-                    mtime: info.mtime,
-                    ctime: info.ctime,
-                    crtime: info.crtime,
-                    kind: FileType::Directory,
-                    perm: info.perm,
-                    nlink: 0,
-                    uid: 501,
-                    gid: 20,
-                    rdev: 0,
-                    flags: 0,
-                })
-            },
-            Err(_error) => reply.error(ENOENT)
-        }
+        self.spawn(move |state| {
+            let path = match state.filestation_filesystem.get_path_for_ino(ino) {
+                Ok(path) => path,
+                Err(error) => {
+                    reply.error(error.to_errno());
+                    return;
+                }
+            };
+
+            debug!("getattr {}", path);
+
+            let info_result = state.filestation_filesystem.get_info(&path);
+
+            match info_result {
+                Ok(info) => reply.attr(&state.attr_ttl, &state.file_attr(&info)),
+                Err(error) => reply.error(error.to_errno())
+            }
+        });
     }
 
     fn lookup(&mut self, _req: &fuser::Request<'_>, parent: u64, name: &OsStr, reply: fuser::ReplyEntry) {
-        let parent_path_result = self.filestation_filesystem.get_path_for_ino(parent);
-        if parent_path_result.is_err() {
-            reply.error(ENOSYS);
-            return;
-        }
-        let parent_path: String = parent_path_result.unwrap();
+        // FileStation only knows UTF-8 names, so anything else cannot exist on the NAS.
+        let name = match name.to_str() {
+            Some(name) => name.to_string(),
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        self.spawn(move |state| {
+            let parent_path = match state.filestation_filesystem.get_path_for_ino(parent) {
+                Ok(parent_path) => parent_path,
+                Err(error) => {
+                    reply.error(error.to_errno());
+                    return;
+                }
+            };
         
-        let mut path = format!("{}/{}", parent_path, name.to_str().unwrap());
-        path = path.replace("//", "/");
+            let mut path = format!("{}/{}", parent_path, name);
+            path = path.replace("//", "/");
 
-        let info_result = self.filestation_filesystem.get_info(&path);
+            let info_result = state.filestation_filesystem.get_info(&path);
+
+            if let Err(error) = info_result {
+                if error.is_not_found() && !state.negative_ttl.is_zero() {
+                    // An entry with inode 0 lets the kernel remember that the name does not exist.
+                    reply.entry(&state.negative_ttl, &Self::negative_attr(), 0);
+                } else {
+                    reply.error(error.to_errno());
+                }
+
+                return;
+            }
+            let info = info_result.unwrap();
+            state.filestation_filesystem.increment_lookup_count(&info);
 
-        if info_result.is_err() {
-            reply.error(info_result.err().unwrap());
+            reply.entry(&state.entry_ttl, &state.file_attr(&info), 0);
+        });
+    }
+
+    fn readlink(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyData) {
+        self.spawn(move |state| {
+            let path = match state.filestation_filesystem.get_path_for_ino(ino) {
+                Ok(path) => path,
+                Err(error) => {
+                    reply.error(error.to_errno());
+                    return;
+                }
+            };
+
+            match state.filestation_filesystem.get_info(&path) {
+                Ok(info) => match info.link_target {
+                    Some(target) => reply.data(Self::relative_link_target(&path, &target).as_bytes()),
+                    None => reply.error(EINVAL)
+                },
+                Err(error) => reply.error(error.to_errno())
+            }
+        });
+    }
+
+    fn getxattr(&mut self, _req: &fuser::Request<'_>, ino: u64, name: &OsStr, size: u32, reply: fuser::ReplyXattr) {
+        let name = name.to_owned();
+
+        self.spawn(move |state| {
+            let attribute_name = match name.to_str().and_then(|name| name.strip_prefix(XATTR_PREFIX)) {
+                Some(attribute_name) => attribute_name,
+                None => {
+                    reply.error(ENODATA);
+                    return;
+                }
+            };
+
+            let info = match state.filestation_filesystem.get_path_for_ino(ino) {
+                Ok(path) => match state.filestation_filesystem.get_info(&path) {
+                    Ok(info) => info,
+                    Err(error) => {
+                        reply.error(error.to_errno());
+                        return;
+                    }
+                },
+                Err(error) => {
+                    reply.error(error.to_errno());
+                    return;
+                }
+            };
+
+            match state.xattr_value(&info, attribute_name) {
+                Ok(value) => Self::reply_xattr(reply, size, &value),
+                Err(error) => reply.error(error)
+            }
+        });
+    }
+
+    fn listxattr(&mut self, _req: &fuser::Request<'_>, ino: u64, size: u32, reply: fuser::ReplyXattr) {
+        self.spawn(move |state| {
+            let info = match state.filestation_filesystem.get_path_for_ino(ino) {
+                Ok(path) => match state.filestation_filesystem.get_info(&path) {
+                    Ok(info) => info,
+                    Err(error) => {
+                        reply.error(error.to_errno());
+                        return;
+                    }
+                },
+                Err(error) => {
+                    reply.error(error.to_errno());
+                    return;
+                }
+            };
+
+            let mut names: Vec<u8> = Vec::new();
+            for name in Self::xattr_names(&info) {
+                names.extend_from_slice(XATTR_PREFIX.as_bytes());
+                names.extend_from_slice(name.as_bytes());
+                names.push(0);
+            }
+
+            Self::reply_xattr(reply, size, &names);
+        });
+    }
+
+    fn forget(&mut self, _req: &fuser::Request<'_>, ino: u64, nlookup: u64) {
+        if self.state.filestation_filesystem.forget(ino, nlookup) {
+            self.state.md5_sums.lock().unwrap().remove(&ino);
+        }
+    }
+
+    fn batch_forget(&mut self, _req: &fuser::Request<'_>, nodes: &[fuse_forget_one]) {
+        for node in nodes {
+            if self.state.filestation_filesystem.forget(node.nodeid, node.nlookup) {
+                self.state.md5_sums.lock().unwrap().remove(&node.nodeid);
+            }
+        }
+    }
+
+    fn rename(
+            &mut self,
+            _req: &fuser::Request<'_>,
+            parent: u64,
+            name: &OsStr,
+            newparent: u64,
+            newname: &OsStr,
+            _flags: u32,
+            reply: fuser::ReplyEmpty,
+        ) {
+        // FileStation can only rename in place, moving between folders is a copy.
+        if parent != newparent {
+            reply.error(EXDEV);
             return;
         }
-        let info = info_result.unwrap();
 
-        let file_type: FileType;
-        if info.is_dir {
-            file_type = FileType::Directory;
-        } else {
-            file_type = FileType::RegularFile;
-        }
-
-        let ttl = Duration::from_secs(10);
-        reply.entry(
-            &ttl,
-            &FileAttr {
-                ino: info.ino,
-                size: info.size,
-                blksize: self.block_size,
-                blocks: self.size2blocks(info.size),
-                atime: info.atime,
-                mtime: info.mtime,
-                ctime: info.ctime,
-                crtime: info.crtime,
-                kind: file_type,
-                perm: info.perm,
-                nlink: 0,
-                uid: 501,
-                gid: 20,
-                rdev: 0,
-                flags: 0,
-            },
-            0);
+        // FileStation only knows UTF-8 names.
+        let (name, newname) = match (name.to_str(), newname.to_str()) {
+            (Some(name), Some(newname)) => (name.to_string(), newname.to_string()),
+            _ => {
+                reply.error(EINVAL);
+                return;
+            }
+        };
+
+        self.spawn(move |state| {
+            let parent_path = match state.filestation_filesystem.get_path_for_ino(parent) {
+                Ok(parent_path) => parent_path,
+                Err(error) => {
+                    reply.error(error.to_errno());
+                    return;
+                }
+            };
+
+            let mut path = format!("{}/{}", parent_path, name);
+            path = path.replace("//", "/");
+
+            match state.filestation_filesystem.rename(&path, &newname) {
+                Ok(()) => reply.ok(),
+                Err(error) => {
+                    error!("An error occured while trying to rename file. {}", error);
+                    reply.error(error.to_errno());
+                }
+            }
+        });
     }
 
-    fn open(&mut self, _req: &fuser::Request<'_>, ino: u64, _flags: i32, reply: fuser::ReplyOpen) {
-        let path = self.filestation_filesystem.get_path_for_ino(ino).unwrap();
-        
-        match self.filestation_filesystem.get_info(&path) {
-            Ok(info) => {
-                let handle = self.next_handle;
-                self.next_handle += 1;
-                self.file_handles.insert(handle, info);
+    fn access(&mut self, req: &fuser::Request<'_>, ino: u64, mask: i32, reply: fuser::ReplyEmpty) {
+        let uid = req.uid();
+        let gid = req.gid();
 
-                reply.opened(handle, 0);
-            },
-            Err(error) => {
-                error!("An error occured while trying to open file. {}", error);
-                reply.error(error);
+        self.spawn(move |state| {
+            let path_result = state.filestation_filesystem.get_path_for_ino(ino);
+            if path_result.is_err() {
+                reply.error(ENOENT);
+                return;
             }
-        }
+            let path: String = path_result.unwrap();
+
+            let info = match state.filestation_filesystem.get_info(&path) {
+                Ok(info) => info,
+                Err(error) => {
+                    reply.error(error.to_errno());
+                    return;
+                }
+            };
+
+            if mask == F_OK {
+                reply.ok();
+                return;
+            }
+
+            if !Self::is_permitted(&state.file_attr(&info), uid, gid, mask) {
+                reply.error(EACCES);
+                return;
+            }
+
+            if mask & W_OK != 0 {
+                let result = if info.is_dir {
+                    // Writing to a folder means creating entries in it.
+                    state.filestation_filesystem.check_write_permission(&path, ACCESS_PROBE_NAME, false)
+                } else {
+                    let (folder_path, name) = Self::split_path(&path);
+                    state.filestation_filesystem.check_write_permission(folder_path, name, true)
+                };
+
+                if let Err(error) = result {
+                    error!("Write access to {} was denied. {}", path, error);
+                    reply.error(error.to_errno());
+                    return;
+                }
+            }
+
+            reply.ok();
+        });
+    }
+
+    fn mkdir(
+            &mut self,
+            _req: &fuser::Request<'_>,
+            parent: u64,
+            name: &OsStr,
+            _mode: u32,
+            _umask: u32,
+            reply: fuser::ReplyEntry,
+        ) {
+        // FileStation only knows UTF-8 names.
+        let name = match name.to_str() {
+            Some(name) => name.to_string(),
+            None => {
+                reply.error(EINVAL);
+                return;
+            }
+        };
+
+        self.spawn(move |state| {
+            let parent_path_result = state.filestation_filesystem.get_path_for_ino(parent);
+            if parent_path_result.is_err() {
+                reply.error(ENOENT);
+                return;
+            }
+            let parent_path: String = parent_path_result.unwrap();
+            let name_str = name.as_str();
+
+            if let Err(error) = state.filestation_filesystem.check_write_permission(&parent_path, name_str, false) {
+                error!("Creating {} in {} was denied. {}", name_str, parent_path, error);
+                reply.error(error.to_errno());
+                return;
+            }
+
+            match state.filestation_filesystem.create_folder(&parent_path, name_str) {
+                Ok(info) => {
+                    state.filestation_filesystem.increment_lookup_count(&info);
+
+                    reply.entry(&state.entry_ttl, &state.file_attr(&info), 0);
+                },
+                Err(error) => {
+                    error!("An error occured while trying to create a folder. {}", error);
+                    reply.error(error.to_errno());
+                }
+            }
+        });
+    }
+
+    fn create(
+            &mut self,
+            _req: &fuser::Request<'_>,
+            parent: u64,
+            name: &OsStr,
+            _mode: u32,
+            _umask: u32,
+            _flags: i32,
+            reply: fuser::ReplyCreate,
+        ) {
+        // FileStation only knows UTF-8 names.
+        let name = match name.to_str() {
+            Some(name) => name.to_string(),
+            None => {
+                reply.error(EINVAL);
+                return;
+            }
+        };
+
+        self.spawn(move |state| {
+            let parent_path = match state.filestation_filesystem.get_path_for_ino(parent) {
+                Ok(parent_path) => parent_path,
+                Err(error) => {
+                    reply.error(error.to_errno());
+                    return;
+                }
+            };
+
+            // A create that would be denied says so, rather than claiming creating files is unsupported.
+            if let Err(error) = state.filestation_filesystem.check_write_permission(&parent_path, &name, false) {
+                error!("Creating {} in {} was denied. {}", name, parent_path, error);
+                reply.error(error.to_errno());
+                return;
+            }
+
+            // Uploads are not supported yet.
+            reply.error(ENOSYS);
+        });
+    }
+
+    fn open(&mut self, _req: &fuser::Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
+        self.spawn(move |state| {
+            let path = match state.filestation_filesystem.get_path_for_ino(ino) {
+                Ok(path) => path,
+                Err(error) => {
+                    reply.error(error.to_errno());
+                    return;
+                }
+            };
+
+            // Fail before the application starts writing, rather than halfway through.
+            if flags & O_ACCMODE != O_RDONLY {
+                let (folder_path, name) = Self::split_path(&path);
+
+                if let Err(error) = state.filestation_filesystem.check_write_permission(folder_path, name, true) {
+                    error!("Writing to {} was denied. {}", path, error);
+                    reply.error(error.to_errno());
+                    return;
+                }
+            }
+
+            match state.filestation_filesystem.get_info(&path) {
+                Ok(info) => {
+                    let mut handles = state.handles.lock().unwrap();
+                    let handle = handles.allocate();
+                    handles.files.insert(handle, info);
+
+                    reply.opened(handle, 0);
+                },
+                Err(error) => {
+                    error!("An error occured while trying to open file. {}", error);
+                    reply.error(error.to_errno());
+                }
+            }
+        });
     }
 
     fn read(
@@ -149,98 +769,300 @@ impl Filesystem for UnixFileSystemHandler {
             reply: fuser::ReplyData,
         ) {
         
-        if !self.file_handles.contains_key(&fh) {
-            reply.error(EEXIST);
-            return;
-        }
+        let path = match self.state.handles.lock().unwrap().files.get(&fh) {
+            Some(info) => info.path.clone(),
+            None => {
+                reply.error(EEXIST);
+                return;
+            }
+        };
 
-        let info = &self.file_handles[&fh];
+        self.spawn(move |state| {
+            let mut buffer = vec![0_u8; size as usize];
+            match state.filestation_filesystem.read_bytes(&path, offset, &mut buffer) {
+                Ok(_size) => reply.data(&buffer),
+                Err(error) => reply.error(error.to_errno())
+            }
+        });
+    }
 
-        let mut buffer = vec![0 as u8; size as usize];
-        match self.filestation_filesystem.read_bytes(&info.path, offset, &mut buffer) {
-            Ok(_size) => reply.data(&buffer),
-            Err(error) => reply.error(error)
-        }
+    fn release(
+            &mut self,
+            _req: &fuser::Request<'_>,
+            _ino: u64,
+            fh: u64,
+            _flags: i32,
+            _lock_owner: Option<u64>,
+            _flush: bool,
+            reply: fuser::ReplyEmpty,
+        ) {
+        self.state.handles.lock().unwrap().files.remove(&fh);
+        reply.ok();
+    }
+
+    fn opendir(&mut self, _req: &fuser::Request<'_>, ino: u64, _flags: i32, reply: fuser::ReplyOpen) {
+        self.spawn(move |state| {
+            let path = match state.filestation_filesystem.get_path_for_ino(ino) {
+                Ok(path) => path,
+                Err(_) => {
+                    reply.error(ENOENT);
+                    return;
+                }
+            };
+
+            let info = match state.filestation_filesystem.get_info(&path) {
+                Ok(info) => info,
+                Err(error) => {
+                    error!("An error occured while trying to open folder. {}", error);
+                    reply.error(error.to_errno());
+                    return;
+                }
+            };
+
+            let (parent_path, _name) = Self::split_path(&path);
+            let parent_ino = if path == "/" {
+                ino
+            } else {
+                match state.filestation_filesystem.get_ino_for_path(parent_path) {
+                    Ok(parent_ino) => parent_ino,
+                    Err(error) => {
+                        reply.error(error.to_errno());
+                        return;
+                    }
+                }
+            };
+
+            let mut handles = state.handles.lock().unwrap();
+            let handle = handles.allocate();
+            handles.directories.insert(handle, Arc::new(Mutex::new(DirectoryHandle {
+                path,
+                info,
+                parent_ino,
+                pages: HashMap::new(),
+                total: None,
+            })));
+
+            reply.opened(handle, 0);
+        });
     }
 
     fn readdir(
             &mut self,
             _req: &fuser::Request<'_>,
-            ino: u64,
-            _fh: u64,
+            _ino: u64,
+            fh: u64,
             offset: i64,
             mut reply: fuser::ReplyDirectory,
         ) {
-            let path_result = self.filestation_filesystem.get_path_for_ino(ino);
-            if path_result.is_err() {
-                reply.error(ENOSYS);
+        let directory = match self.state.handles.lock().unwrap().directories.get(&fh) {
+            Some(directory) => directory.clone(),
+            None => {
+                reply.error(EBADF);
                 return;
             }
-            let path: String = path_result.unwrap();
-    
-            let result = self.filestation_filesystem.list_files(&path);
-            match result {
-                Ok(files) => {
-                    let mut is_next = false;
-                    if offset == 0 {
-                        is_next = true;
+        };
+
+        self.spawn(move |state| {
+            let mut directory = directory.lock().unwrap();
+
+            // The offset of each entry is its position plus one, so the kernel resumes right after it.
+            let mut index = offset as u64;
+            loop {
+                match state.get_directory_entry(&mut directory, index) {
+                    Ok(Some((name, info))) => {
+                        index += 1;
+
+                        if reply.add(info.ino, index as i64, Self::file_type(&info), &name) {
+                            break;
+                        }
+                    },
+                    Ok(None) => break,
+                    Err(error) => {
+                        reply.error(error);
+                        return;
                     }
-    
-                    for file in files.iter() {
-                        if !is_next {
-                            if offset == (file.ino as i64) {
-                                is_next = true;
-                            }
-                            continue;
+                }
+            }
+
+            reply.ok();
+        });
+    }
+
+    fn readdirplus(
+            &mut self,
+            _req: &fuser::Request<'_>,
+            _ino: u64,
+            fh: u64,
+            offset: i64,
+            mut reply: fuser::ReplyDirectoryPlus,
+        ) {
+        let directory = match self.state.handles.lock().unwrap().directories.get(&fh) {
+            Some(directory) => directory.clone(),
+            None => {
+                reply.error(EBADF);
+                return;
+            }
+        };
+
+        self.spawn(move |state| {
+            let mut directory = directory.lock().unwrap();
+            let ttl = state.entry_ttl;
+
+            let mut index = offset as u64;
+            loop {
+                match state.get_directory_entry(&mut directory, index) {
+                    Ok(Some((name, info))) => {
+                        index += 1;
+
+                        if reply.add(info.ino, index as i64, &name, &ttl, &state.file_attr(&info), 0) {
+                            break;
                         }
-    
-                        let file_type: FileType;
-                        if file.is_dir {
-                            file_type = FileType::Directory;
-                        } else {
-                            file_type = FileType::RegularFile;
+
+                        // Every entry handed to the kernel with its attributes counts as a lookup, except "." and "..".
+                        if index > 2 {
+                            state.filestation_filesystem.increment_lookup_count(&info);
                         }
-    
-                        let _ = reply.add(file.ino, file.ino as i64, file_type, file.name.clone());
-                        reply.ok();
-    
+                    },
+                    Ok(None) => break,
+                    Err(error) => {
+                        reply.error(error);
                         return;
                     }
-    
-                    reply.ok();
                 }
-                Err(err) => reply.error(err)
             }
+
+            reply.ok();
+        });
     }
+
+    fn releasedir(&mut self, _req: &fuser::Request<'_>, _ino: u64, fh: u64, _flags: i32, reply: fuser::ReplyEmpty) {
+        self.state.handles.lock().unwrap().directories.remove(&fh);
+        reply.ok();
+    }
+}
+
+/// Periodically looks for changes other users made on the NAS, for as long as the file system is mounted,
+/// and tells the kernel to forget the entries and attributes it cached for them.
+///
+/// Files changed in place are picked up once the kernel's TTLs expire.  Stale pages are dropped then,
+/// thanks to `FUSE_AUTO_INVAL_DATA`.
+fn spawn_change_poller(filestation_filesystem: Weak<FileStationFileSystem>, notifier: Notifier, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        match filestation_filesystem.upgrade() {
+            Some(filestation_filesystem) => {
+                let changes = filestation_filesystem.poll_remote_changes();
+                let mut changed_parents: HashSet<u64> = HashSet::new();
+
+                for change in changes {
+                    info!("{} changed on the NAS.", change.path);
+
+                    // ENOENT only means the kernel did not cache the entry in the first place.
+                    if let Err(error) = notifier.inval_entry(change.parent_ino, OsStr::new(&change.name)) {
+                        if error.raw_os_error() != Some(ENOENT) {
+                            error!("Could not invalidate the entry for {}: {}", change.path, error);
+                        }
+                    }
+                    if let Some(ino) = change.ino {
+                        if let Err(error) = notifier.inval_inode(ino, 0, 0) {
+                            if error.raw_os_error() != Some(ENOENT) {
+                                error!("Could not invalidate the inode for {}: {}", change.path, error);
+                            }
+                        }
+                    }
+                    changed_parents.insert(change.parent_ino);
+                }
+
+                // The listings of the folders changed too.
+                for ino in changed_parents {
+                    if let Err(error) = notifier.inval_inode(ino, 0, 0) {
+                        if error.raw_os_error() != Some(ENOENT) {
+                            error!("Could not invalidate the folder with inode {}: {}", ino, error);
+                        }
+                    }
+                }
+            },
+            None => break
+        }
+    });
 }
 
 pub struct UnixFuseFileSystem {
     hostname: String,
     port: u16,
     secured: bool,
+    options: Options,
 }
 
 impl FuseFileSystem for UnixFuseFileSystem {
-    fn new(hostname: &str, port: u16, secured: bool, _debug: bool) -> UnixFuseFileSystem {
+    fn new(hostname: &str, port: u16, secured: bool, _debug: bool, options: Options) -> UnixFuseFileSystem {
         UnixFuseFileSystem {
             hostname: hostname.to_string(),
             port,
-            secured
+            secured,
+            options
         }
     }
 
     fn mount(&mut self, mount_point: &str, username: &str, password: &str) {
-        let mut filestation_filesystem = FileStationFileSystem::new(
-            &self.hostname,
-            self.port,
-            self.secured,
-        ).unwrap();
-        filestation_filesystem.login(username, password).unwrap();
+        let filestation_filesystem = match FileStationFileSystem::new(&self.hostname, self.port, self.secured, &self.options) {
+            Ok(filestation_filesystem) => filestation_filesystem,
+            Err(error) => {
+                error!("Could not connect to {}: {}", self.hostname, error);
+                return;
+            }
+        };
+        if let Err(error) = filestation_filesystem.login(username, password, || self.options.otp_code.clone().or_else(read_otp_code)) {
+            error!("Could not log in to {}: {}", self.hostname, error);
+            return;
+        }
 
+        let filestation_filesystem = Arc::new(filestation_filesystem);
+        let poller_filesystem = Arc::downgrade(&filestation_filesystem);
+
+        let handler = UnixFileSystemHandler::new(filestation_filesystem, &self.options);
         let options = vec![MountOption::RW, MountOption::FSName("SYNO_FileStation".to_string())];
-        fuser::mount2(UnixFileSystemHandler::new(filestation_filesystem), mount_point, &options).unwrap();
+        let mut session = match Session::new(handler, Path::new(mount_point), &options) {
+            Ok(session) => session,
+            Err(error) => {
+                error!("Could not mount {}: {}", mount_point, error);
+                return;
+            }
+        };
+
+        if !self.options.poll_interval.is_zero() {
+            spawn_change_poller(poller_filesystem, session.notifier(), self.options.poll_interval);
+        }
+
+        if let Err(error) = session.run() {
+            error!("An error occurred while serving {}: {}", mount_point, error);
+        }
     }
 
     fn unmount(&self) {
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnixFileSystemHandler;
+
+    #[test]
+    fn relative_link_target_in_the_same_folder() {
+        assert_eq!(UnixFileSystemHandler::relative_link_target("/home/docs/link", "/home/docs/report.pdf"), "report.pdf");
+        assert_eq!(UnixFileSystemHandler::relative_link_target("/home/docs/link", "/home/docs"), ".");
+    }
+
+    #[test]
+    fn relative_link_target_in_a_sibling_folder() {
+        assert_eq!(UnixFileSystemHandler::relative_link_target("/home/docs/link", "/home/photos/2024/beach.jpg"), "../photos/2024/beach.jpg");
+        assert_eq!(UnixFileSystemHandler::relative_link_target("/home/docs/deep/link", "/home/report.pdf"), "../../report.pdf");
+    }
+
+    #[test]
+    fn relative_link_target_in_another_share() {
+        assert_eq!(UnixFileSystemHandler::relative_link_target("/home/link", "/photo/2024/beach.jpg"), "../photo/2024/beach.jpg");
+        assert_eq!(UnixFileSystemHandler::relative_link_target("/home/docs/link", "/photo"), "../../photo");
+    }
+}
@@ -1,5 +1,7 @@
 use crate::filesystems::FuseFileSystem;
-use crate::synology_api::FileStationFileSystem;
+use crate::filesystems::fuse_fileystem::read_otp_code;
+use crate::options::Options;
+use crate::synology_api::{FileStationFileSystem, SynologyError};
 
 use std::{time::SystemTime, time::Duration, thread};
 use dokan::{
@@ -20,6 +22,7 @@ use dokan::{
     IO_SECURITY_CONTEXT, MountFlags
 };
 use widestring::{U16CString, UCString, U16CStr};
+use log::error;
 use winapi::um::winnt;
 
 #[derive(Debug)]
@@ -52,8 +55,8 @@ impl WindowsFileSystemHandler {
 		}
     }
 
-	fn login(& mut self, username: &str, password: &str) -> Result<(), i32> {
-		self.filestation_filesystem.login(username, password)
+	fn login(& mut self, username: &str, password: &str, otp_code: Option<String>) -> Result<(), SynologyError> {
+		self.filestation_filesystem.login(username, password, || otp_code.or_else(read_otp_code))
 	}
 
 	fn get_filesystem_entry(&self, file_name: &str) -> Result<WindowsFileSystemEntry, i32> {
@@ -86,13 +89,7 @@ impl WindowsFileSystemHandler {
 					is_dir: info.is_dir
 				})
 			},
-			Err(error) => {
-				if error == 408 {
-					return Err(winapi::shared::ntstatus::STATUS_OBJECT_NAME_NOT_FOUND);
-				}
-
-				return Err(error);
-			}
+			Err(error) => Err(error.to_ntstatus())
 		}
 	}
 }
@@ -163,14 +160,14 @@ impl<'c, 'h: 'c> FileSystemHandler<'c, 'h> for WindowsFileSystemHandler {
 						});
 
 						if result.is_err() {
-							return Err(-2);
+							return Err(winapi::shared::ntstatus::STATUS_BUFFER_OVERFLOW);
 						}
 					}
 				}
 
 				return Ok(());
 			},
-			Err(error) => Err(error)
+			Err(error) => Err(error.to_ntstatus())
 		};
 	}
 
@@ -185,7 +182,7 @@ impl<'c, 'h: 'c> FileSystemHandler<'c, 'h> for WindowsFileSystemHandler {
 					free_byte_count: freespace,
 					available_byte_count: freespace,
 			}),
-			Err(err) => Err(err)
+			Err(err) => Err(err.to_ntstatus())
 		}
 	}
 
@@ -268,12 +265,12 @@ impl<'c, 'h: 'c> FileSystemHandler<'c, 'h> for WindowsFileSystemHandler {
 		
 				Ok(size as u32)
 			},
-			Err(error) => Err(error)
+			Err(error) => Err(error.to_ntstatus())
 		}
 	}
 
     fn unmounted(&'h self, _info: &OperationInfo<'c, 'h, Self>) -> OperationResult<()> {
-        self.filestation_filesystem.logout()
+        self.filestation_filesystem.logout().map_err(SynologyError::to_ntstatus)
 	}
 }
 
@@ -283,16 +280,18 @@ pub struct WindowsFuseFileSystem {
     secured: bool,
     mount_point: Option<UCString<u16>>,
 	debug: bool,
+	options: Options,
 }
 
 impl FuseFileSystem for WindowsFuseFileSystem {
-    fn new(hostname: &str, port: u16, secured: bool, debug: bool) -> WindowsFuseFileSystem {
+    fn new(hostname: &str, port: u16, secured: bool, debug: bool, options: Options) -> WindowsFuseFileSystem {
         WindowsFuseFileSystem {
             hostname: hostname.to_string(),
             port,
             secured,
             mount_point: Default::default(),
-			debug
+			debug,
+			options
         }
     }
 
@@ -306,16 +305,24 @@ impl FuseFileSystem for WindowsFuseFileSystem {
         let filestation_filesystem = FileStationFileSystem::new(
             &self.hostname,
             self.port,
-            self.secured
+            self.secured,
+            &self.options
         );
 
 		let username_string = username.to_string();
 		let password_string = password.to_string();
+		let otp_code = self.options.otp_code.clone();
 
 		let debug = self.debug;
 
         let executor = move || {
-            let mut handler = WindowsFileSystemHandler::new(filestation_filesystem.unwrap());
+            let mut handler = match filestation_filesystem {
+				Ok(filestation_filesystem) => WindowsFileSystemHandler::new(filestation_filesystem),
+				Err(error) => {
+					error!("Could not connect: {}", error);
+					return;
+				}
+			};
 			let mut flags = MountFlags::ALT_STREAM | MountFlags::STDERR | MountFlags::NETWORK;
 			if debug {
 				flags |= MountFlags::DEBUG;
@@ -327,7 +334,10 @@ impl FuseFileSystem for WindowsFuseFileSystem {
                 ..Default::default()
             };
 
-			handler.login(username_string.as_str(), password_string.as_str()).unwrap();
+			if let Err(error) = handler.login(username_string.as_str(), password_string.as_str(), otp_code) {
+				error!("Could not log in: {}", error);
+				return;
+			}
             let mut mounter = FileSystemMounter::new(&handler, &cstr_mount, &options);
             let _ = mounter.mount().unwrap();
         };
@@ -2,45 +2,113 @@ extern crate pretty_env_logger;
 
 use std::env;
 use std::io::{stdin, stdout, Read, Write};
-use log::info;
+use std::process::exit;
+use log::{error, info};
 use filesystems::FuseFileSystem;
+use filesystems::fuse_fileystem::read_otp_code;
+use options::Options;
+use synology_api::{FileStationFileSystem, SearchQuery};
 
 mod filesystems;
+mod options;
 mod synology_api;
 
 fn pause() {
     let mut stdout = stdout();
-    stdout.write(b"Press Enter to continue...").unwrap();
+    stdout.write_all(b"Press Enter to continue...").unwrap();
     stdout.flush().unwrap();
-    stdin().read(&mut [0]).unwrap();
+    stdin().read_exact(&mut [0]).unwrap();
 }
 
 #[cfg(target_family = "windows")]
-fn init_fuse_filesystem(hostname: &str, port: u16, secured: bool, debug_mode: bool) -> filesystems::WindowsFuseFileSystem {
+fn init_fuse_filesystem(hostname: &str, port: u16, secured: bool, debug_mode: bool, options: Options) -> filesystems::WindowsFuseFileSystem {
     filesystems::WindowsFuseFileSystem::new(
         hostname,
         port,
         secured,
-        debug_mode
+        debug_mode,
+        options
     )
 }
 
 #[cfg(target_family = "unix")]
-fn init_fuse_filesystem(hostname: &str, port: u16, secured: bool, debug_mode: bool) -> filesystems::UnixFuseFileSystem {
+fn init_fuse_filesystem(hostname: &str, port: u16, secured: bool, debug_mode: bool, options: Options) -> filesystems::UnixFuseFileSystem {
     filesystems::UnixFuseFileSystem::new(
         hostname,
         port,
         secured,
-        debug_mode
+        debug_mode,
+        options
     )
 }
 
+/// Searches the NAS instead of mounting it, and prints the path of every match.
+/// The NAS and the account are taken from the same environment variables as in debug mode.
+fn search(args: &[String], options: Options) {
+    let query = match SearchQuery::from_args(&args[2..]) {
+        Ok(query) => query,
+        Err(message) => {
+            eprintln!("Invalid search: {}", message);
+            exit(2);
+        }
+    };
+
+    let hostname = env::var("SYNOLOGY_HOSTNAME").unwrap();
+    let port = env::var("SYNOLOGY_PORT").unwrap().parse::<u16>().unwrap();
+    let secured = env::var("SYNOLOGY_SECURED").unwrap().parse::<bool>().unwrap();
+
+    let username = env::var("SYNOLOGY_USERNAME").unwrap();
+    let password = env::var("SYNOLOGY_PASSWORD").unwrap();
+
+    let filestation_filesystem = match FileStationFileSystem::new(&hostname, port, secured, &options) {
+        Ok(filestation_filesystem) => filestation_filesystem,
+        Err(error) => {
+            eprintln!("Could not connect to {}: {}", hostname, error);
+            exit(1);
+        }
+    };
+    if let Err(error) = filestation_filesystem.login(&username, &password, || options.otp_code.clone().or_else(read_otp_code)) {
+        eprintln!("Could not log in to {}: {}", hostname, error);
+        exit(1);
+    }
+
+    let result = filestation_filesystem.search(&query);
+    if let Err(error) = filestation_filesystem.logout() {
+        error!("Could not log out of {}: {}", hostname, error);
+    }
+
+    match result {
+        Ok(paths) => {
+            for path in paths {
+                println!("{}", path);
+            }
+        },
+        Err(error) => {
+            eprintln!("The search failed: {}", error);
+            exit(1);
+        }
+    }
+}
+
 fn main() {
     pretty_env_logger::init();
 
     let args: Vec<String> = env::args().collect();
 
-    let debug_mode = args[1] == "--debug";
+    let options = match Options::from_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("Invalid option: {}", message);
+            exit(2);
+        }
+    };
+
+    if args.get(1).map(String::as_str) == Some("search") {
+        search(&args, options);
+        return;
+    }
+
+    let debug_mode = args.iter().any(|arg| arg == "--debug");
 
     let hostname: String;
     let port: u16;
@@ -74,7 +142,8 @@ fn main() {
         &hostname,
         port,
         secured,
-        debug_mode);
+        debug_mode,
+        options);
 
     info!("Mounting Synology NAS...");
 
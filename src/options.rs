@@ -0,0 +1,184 @@
+use std::{env, path::PathBuf, time::Duration};
+
+use log::error;
+
+/// Where the permission bits reported for a file come from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PermissionMode {
+    /// Use the ACL flags when the NAS says ACLs are in effect, and the POSIX bits otherwise.
+    #[default]
+    Auto,
+    /// Always use the POSIX bits.
+    Posix,
+    /// Always derive the bits from the ACL flags of the logged-in user.
+    Acl,
+}
+
+/// Mount options.  Each option can be given on the command line as `--name=value`,
+/// or through the matching `SYNOLOGY_NAME` environment variable.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Owner reported for files whose owner is not in the owner map.  Defaults to the mounting user.
+    pub uid: Option<u32>,
+    /// Group reported for files whose group is not in the owner map.  Defaults to the mounting user's group.
+    pub gid: Option<u32>,
+    /// File mapping the users and groups of the NAS to local ids.
+    pub owner_map: Option<PathBuf>,
+    /// Bits cleared from the permissions reported by the NAS.
+    pub umask: Option<u16>,
+    /// Where the permission bits come from.
+    pub permissions: PermissionMode,
+    /// How long the kernel may cache file attributes.
+    pub attr_ttl: Duration,
+    /// How long the kernel may cache names it looked up.
+    pub entry_ttl: Duration,
+    /// How long a name that does not exist is remembered as missing.
+    pub negative_ttl: Duration,
+    /// How often open folders are checked for changes made on the NAS.  Zero disables polling.
+    pub poll_interval: Duration,
+    /// How long a lookup waits for others to share a single getinfo call.  Zero disables batching.
+    pub batch_window: Duration,
+    /// How long connecting to the NAS may take.
+    pub connect_timeout: Duration,
+    /// How long a request may wait for the NAS to answer, or a download for its next chunk.
+    pub request_timeout: Duration,
+    /// How long unused connections are kept open.
+    pub idle_timeout: Duration,
+    /// Talk HTTP/2 to the NAS right away, without negotiating it first.
+    pub http2: bool,
+    /// How many times a request that is safe to repeat is attempted before giving up.
+    pub retry_attempts: u32,
+    /// How long to wait before the first retry.  The wait doubles with every further retry.
+    pub retry_delay: Duration,
+    /// Code from the authenticator app, for accounts with 2-step verification.  Asked for when needed otherwise.
+    pub otp_code: Option<String>,
+    /// PEM file with the certificates of further authorities to trust, such as an internal CA.
+    pub ca_bundle: Option<PathBuf>,
+    /// SHA-256 fingerprint of the certificate the NAS must present, as lowercase hex.
+    pub fingerprint: Option<String>,
+    /// Accept any certificate, even one that is expired or was issued for someone else.
+    pub accept_invalid_certificates: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            uid: None,
+            gid: None,
+            owner_map: None,
+            umask: None,
+            permissions: PermissionMode::default(),
+            attr_ttl: Duration::from_secs(10),
+            entry_ttl: Duration::from_secs(10),
+            negative_ttl: Duration::from_secs(10),
+            poll_interval: Duration::from_secs(30),
+            batch_window: Duration::from_millis(2),
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(90),
+            http2: false,
+            retry_attempts: 4,
+            retry_delay: Duration::from_millis(250),
+            otp_code: None,
+            ca_bundle: None,
+            fingerprint: None,
+            accept_invalid_certificates: false,
+        }
+    }
+}
+
+impl Options {
+    /// Invalid values are logged and replaced by their defaults, except for a fingerprint: mounting
+    /// without the certificate pinned the user asked for is refused instead.
+    pub fn from_args(args: &[String]) -> Result<Options, String> {
+        let defaults = Options::default();
+
+        let fingerprint = match get_option(args, "fingerprint") {
+            Some(value) => match parse_fingerprint(value.as_str()) {
+                Some(fingerprint) => Some(fingerprint),
+                None => return Err(format!("'{}' is not a SHA-256 fingerprint", value))
+            },
+            None => None
+        };
+
+        Ok(Options {
+            uid: parse_option(args, "uid", |value| value.parse::<u32>().ok()),
+            gid: parse_option(args, "gid", |value| value.parse::<u32>().ok()),
+            owner_map: parse_option(args, "owner-map", |value| Some(PathBuf::from(value))),
+            umask: parse_option(args, "umask", |value| u16::from_str_radix(value, 8).ok()),
+            permissions: parse_option(args, "permissions", |value| match value {
+                "auto" => Some(PermissionMode::Auto),
+                "posix" => Some(PermissionMode::Posix),
+                "acl" => Some(PermissionMode::Acl),
+                _ => None
+            }).unwrap_or(defaults.permissions),
+            attr_ttl: parse_option(args, "attr-ttl", parse_seconds).unwrap_or(defaults.attr_ttl),
+            entry_ttl: parse_option(args, "entry-ttl", parse_seconds).unwrap_or(defaults.entry_ttl),
+            negative_ttl: parse_option(args, "negative-ttl", parse_seconds).unwrap_or(defaults.negative_ttl),
+            poll_interval: parse_option(args, "poll-interval", parse_seconds).unwrap_or(defaults.poll_interval),
+            batch_window: parse_option(args, "batch-window", parse_seconds).unwrap_or(defaults.batch_window),
+            connect_timeout: parse_option(args, "connect-timeout", parse_seconds).unwrap_or(defaults.connect_timeout),
+            request_timeout: parse_option(args, "request-timeout", parse_seconds).unwrap_or(defaults.request_timeout),
+            idle_timeout: parse_option(args, "idle-timeout", parse_seconds).unwrap_or(defaults.idle_timeout),
+            http2: parse_option(args, "http2", parse_bool).unwrap_or(defaults.http2),
+            retry_attempts: parse_option(args, "retry-attempts", |value| value.parse::<u32>().ok().filter(|attempts| *attempts > 0)).unwrap_or(defaults.retry_attempts),
+            retry_delay: parse_option(args, "retry-delay", parse_seconds).unwrap_or(defaults.retry_delay),
+            otp_code: parse_option(args, "otp-code", |value| Some(value.to_string())),
+            ca_bundle: parse_option(args, "ca-bundle", |value| Some(PathBuf::from(value))),
+            fingerprint,
+            accept_invalid_certificates: parse_option(args, "accept-invalid-certificates", parse_bool).unwrap_or(defaults.accept_invalid_certificates),
+        })
+    }
+}
+
+fn parse_seconds(value: &str) -> Option<Duration> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds >= 0.0 => Some(Duration::from_secs_f64(seconds)),
+        _ => None
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "yes" | "1" => Some(true),
+        "false" | "no" | "0" => Some(false),
+        _ => None
+    }
+}
+
+/// Accepts the hex digits in any case, with or without the colons most tools print between bytes.
+fn parse_fingerprint(value: &str) -> Option<String> {
+    let fingerprint: String = value.chars().filter(|c| *c != ':').collect::<String>().to_lowercase();
+    if fingerprint.len() == 64 && fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(fingerprint)
+    } else {
+        None
+    }
+}
+
+fn get_option(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("--{}=", name);
+
+    for arg in args.iter() {
+        if let Some(value) = arg.strip_prefix(prefix.as_str()) {
+            return Some(value.to_string());
+        }
+    }
+
+    let variable = format!("SYNOLOGY_{}", name.replace('-', "_").to_uppercase());
+    env::var(variable).ok()
+}
+
+fn parse_option<T>(args: &[String], name: &str, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+    match get_option(args, name) {
+        Some(value) => {
+            let parsed = parse(value.as_str());
+            if parsed.is_none() {
+                error!("Ignoring invalid value '{}' for option '{}'.", value, name);
+            }
+
+            parsed
+        },
+        None => None
+    }
+}
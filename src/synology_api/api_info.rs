@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+use log::error;
+
+use super::SynologyError;
+use super::responses::ApiInfo;
+
+/// Versions of each API this client knows how to speak, as (api, oldest, newest).
+const SUPPORTED_VERSIONS: [(&str, u32, u32); 8] = [
+    ("SYNO.API.Auth", 3, 7),
+    ("SYNO.FileStation.List", 2, 2),
+    ("SYNO.FileStation.Download", 2, 2),
+    ("SYNO.FileStation.CheckPermission", 3, 3),
+    ("SYNO.FileStation.CreateFolder", 2, 2),
+    ("SYNO.FileStation.Rename", 2, 2),
+    ("SYNO.FileStation.MD5", 2, 2),
+    ("SYNO.FileStation.Search", 2, 2),
+];
+
+/// Where an API is served, and the version to ask for.
+#[derive(Clone, Debug)]
+pub struct ApiEndpoint {
+    /// Path of the CGI below `/webapi/`.
+    pub path: String,
+    pub version: u32,
+}
+
+/// The APIs the NAS offers, as reported by `SYNO.API.Info`.
+pub struct ApiCatalog {
+    apis: HashMap<String, ApiInfo>,
+}
+
+impl ApiCatalog {
+    pub fn new(apis: HashMap<String, ApiInfo>) -> ApiCatalog {
+        ApiCatalog {
+            apis
+        }
+    }
+
+    /// Picks the newest version of `api` that both the NAS and this client support.
+    pub fn endpoint(&self, api: &str) -> Result<ApiEndpoint, SynologyError> {
+        let info = match self.apis.get(api) {
+            Some(info) => info,
+            None => {
+                error!("The NAS does not offer {}.", api);
+
+                return Err(SynologyError::NoSuchApi);
+            }
+        };
+
+        let (oldest, newest) = match SUPPORTED_VERSIONS.iter().find(|(name, _, _)| *name == api) {
+            Some((_, oldest, newest)) => (*oldest, *newest),
+            None => (info.min_version, info.max_version)
+        };
+
+        let version = newest.min(info.max_version);
+        if version < oldest.max(info.min_version) {
+            error!(
+                "The NAS offers versions {} to {} of {}, but only versions {} to {} are supported.",
+                info.min_version, info.max_version, api, oldest, newest);
+
+            return Err(SynologyError::UnsupportedVersion);
+        }
+
+        Ok(ApiEndpoint {
+            path: info.path.clone(),
+            version,
+        })
+    }
+}
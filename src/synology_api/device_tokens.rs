@@ -0,0 +1,77 @@
+use std::{path::Path, sync::Mutex};
+
+use log::error;
+use sqlite::{Connection, State};
+
+use super::SynologyError;
+use super::inode_table::open_connection;
+
+/// Device tokens the NAS handed out after a 2-step verification, by account.
+///
+/// Logging in with the token of a trusted device skips the verification code.  The table
+/// itself is created by the `FileCache` database migrations.
+pub struct DeviceTokens {
+	connection: Mutex<Connection>,
+}
+
+impl DeviceTokens {
+	pub fn new(db_path: &Path) -> Result<DeviceTokens, SynologyError> {
+		match open_connection(db_path) {
+			Ok(connection) => Ok(DeviceTokens {
+				connection: Mutex::new(connection)
+			}),
+			Err(error) => {
+				error!("An error occurred while opening the device tokens: {}", error);
+
+				Err(SynologyError::Cache)
+			}
+		}
+	}
+
+	/// The token of `account`, if this device is trusted.  A token that cannot be read only means
+	/// being asked for a verification code again.
+	pub fn get(&self, account: &str) -> Option<String> {
+		let connection = self.connection.lock().unwrap();
+
+		match query_device_id(&connection, account) {
+			Ok(device_id) => device_id,
+			Err(error) => {
+				error!("An error occurred while looking up the device token for {}: {}", account, error);
+
+				None
+			}
+		}
+	}
+
+	pub fn set(&self, account: &str, device_id: &str) -> Result<(), SynologyError> {
+		let connection = self.connection.lock().unwrap();
+
+		match store_device_id(&connection, account, device_id) {
+			Ok(()) => Ok(()),
+			Err(error) => {
+				error!("An error occurred while saving the device token for {}: {}", account, error);
+
+				Err(SynologyError::Cache)
+			}
+		}
+	}
+}
+
+fn query_device_id(connection: &Connection, account: &str) -> Result<Option<String>, sqlite::Error> {
+	let mut statement = connection.prepare("SELECT device_id FROM device_tokens WHERE account = ?")?;
+	statement.bind((1, account))?;
+
+	match statement.next()? {
+		State::Row => Ok(Some(statement.read::<String, _>("device_id")?)),
+		State::Done => Ok(None)
+	}
+}
+
+fn store_device_id(connection: &Connection, account: &str, device_id: &str) -> Result<(), sqlite::Error> {
+	let mut statement = connection.prepare("INSERT OR REPLACE INTO device_tokens (account, device_id) VALUES (?, ?)")?;
+	statement.bind((1, account))?;
+	statement.bind((2, device_id))?;
+	statement.next()?;
+
+	Ok(())
+}
@@ -0,0 +1,349 @@
+use std::fmt;
+
+/// Everything that can go wrong when talking to FileStation, or with the local state kept about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SynologyError {
+    // Transport.
+    /// The NAS could not be reached.
+    Connection,
+    /// The NAS did not answer in time.
+    TimedOut,
+    /// The NAS answered with an unexpected HTTP status.
+    Http(u16),
+    /// The NAS answered with something that could not be understood.
+    InvalidResponse,
+    /// No session has been opened yet.
+    NotLoggedIn,
+    /// The certificate of the NAS does not match the pinned one, or the certificates to trust could not be loaded.
+    UntrustedCertificate,
+
+    // Local state.
+    /// The file cache or the inode table could not be read or written.
+    Cache,
+
+    // Common DSM codes.
+    /// 100
+    Unknown,
+    /// 101 and 114
+    MissingParameter,
+    /// 102
+    NoSuchApi,
+    /// 103
+    NoSuchMethod,
+    /// 104
+    UnsupportedVersion,
+    /// 105
+    PermissionDenied,
+    /// 106
+    SessionTimeout,
+    /// 107
+    SessionInterrupted,
+    /// 108
+    UploadFailed,
+    /// 109 to 111, 117 and 118
+    NetworkUnstable,
+    /// 115
+    UploadNotAllowed,
+    /// 116
+    DemoSite,
+    /// 119
+    SessionNotFound,
+
+    // FileStation codes.
+    /// 400
+    InvalidParameter,
+    /// 401
+    OperationFailed,
+    /// 402
+    SystemBusy,
+    /// 403 to 406
+    InvalidUserOrGroup,
+    /// 407
+    NotPermitted,
+    /// 408
+    NotFound,
+    /// 409
+    UnsupportedFileSystem,
+    /// 410
+    RemoteFileSystemUnreachable,
+    /// 411
+    ReadOnlyFileSystem,
+    /// 412 and 413
+    NameTooLong,
+    /// 414
+    AlreadyExists,
+    /// 415
+    QuotaExceeded,
+    /// 416
+    NoSpace,
+    /// 417
+    InputOutput,
+    /// 418 to 420
+    IllegalName,
+    /// 421
+    DeviceBusy,
+    /// 599
+    NoSuchTask,
+    /// 900
+    DeleteFailed,
+    /// 1000 to 1002
+    CopyOrMoveFailed,
+    /// 1003
+    CannotOverwrite,
+    /// 1004
+    FileFolderConflict,
+    /// 1006 and 1007
+    UnsupportedOnFat,
+    /// 1100
+    CreateFolderFailed,
+    /// 1101
+    TooManyFolders,
+    /// 1200
+    RenameFailed,
+
+    // Login codes.
+    /// 400
+    WrongPassword,
+    /// 401
+    AccountDisabled,
+    /// 402 and 407
+    LoginDenied,
+    /// 403
+    OtpRequired,
+    /// 404
+    OtpInvalid,
+    /// 406
+    OtpEnforced,
+    /// 408 to 410
+    PasswordExpired,
+
+    /// Any other code the NAS reported.
+    Api(i32),
+}
+
+impl SynologyError {
+    /// Turns an error code reported by DSM or FileStation into an error.
+    pub fn from_code(code: i32) -> SynologyError {
+        match code {
+            100 => SynologyError::Unknown,
+            101 | 114 => SynologyError::MissingParameter,
+            102 => SynologyError::NoSuchApi,
+            103 => SynologyError::NoSuchMethod,
+            104 => SynologyError::UnsupportedVersion,
+            105 => SynologyError::PermissionDenied,
+            106 => SynologyError::SessionTimeout,
+            107 => SynologyError::SessionInterrupted,
+            108 => SynologyError::UploadFailed,
+            109..=111 | 117 | 118 => SynologyError::NetworkUnstable,
+            115 => SynologyError::UploadNotAllowed,
+            116 => SynologyError::DemoSite,
+            119 => SynologyError::SessionNotFound,
+            400 => SynologyError::InvalidParameter,
+            401 => SynologyError::OperationFailed,
+            402 => SynologyError::SystemBusy,
+            403..=406 => SynologyError::InvalidUserOrGroup,
+            407 => SynologyError::NotPermitted,
+            408 => SynologyError::NotFound,
+            409 => SynologyError::UnsupportedFileSystem,
+            410 => SynologyError::RemoteFileSystemUnreachable,
+            411 => SynologyError::ReadOnlyFileSystem,
+            412 | 413 => SynologyError::NameTooLong,
+            414 => SynologyError::AlreadyExists,
+            415 => SynologyError::QuotaExceeded,
+            416 => SynologyError::NoSpace,
+            417 => SynologyError::InputOutput,
+            418..=420 => SynologyError::IllegalName,
+            421 => SynologyError::DeviceBusy,
+            599 => SynologyError::NoSuchTask,
+            900 => SynologyError::DeleteFailed,
+            1000..=1002 => SynologyError::CopyOrMoveFailed,
+            1003 => SynologyError::CannotOverwrite,
+            1004 => SynologyError::FileFolderConflict,
+            1006 | 1007 => SynologyError::UnsupportedOnFat,
+            1100 => SynologyError::CreateFolderFailed,
+            1101 => SynologyError::TooManyFolders,
+            1200 => SynologyError::RenameFailed,
+            _ => SynologyError::Api(code)
+        }
+    }
+
+    /// Turns an error code reported by SYNO.API.Auth into an error.  Its codes overlap with FileStation's.
+    pub fn from_auth_code(code: i32) -> SynologyError {
+        match code {
+            400 => SynologyError::WrongPassword,
+            401 => SynologyError::AccountDisabled,
+            402 | 407 => SynologyError::LoginDenied,
+            403 => SynologyError::OtpRequired,
+            404 => SynologyError::OtpInvalid,
+            406 => SynologyError::OtpEnforced,
+            408..=410 => SynologyError::PasswordExpired,
+            _ => SynologyError::from_code(code)
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        *self == SynologyError::NotFound
+    }
+
+    /// Whether the same request may well succeed if it is sent again a little later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            SynologyError::Http(status) => (500..600).contains(status),
+            SynologyError::Connection
+                | SynologyError::TimedOut
+                | SynologyError::NetworkUnstable
+                | SynologyError::SystemBusy => true,
+            _ => false
+        }
+    }
+
+    /// Whether logging in again would fix the error.
+    pub fn is_session_error(&self) -> bool {
+        matches!(self, SynologyError::SessionTimeout | SynologyError::SessionInterrupted | SynologyError::SessionNotFound)
+    }
+
+    #[cfg(target_family = "unix")]
+    pub fn to_errno(self) -> i32 {
+        match self {
+            SynologyError::Connection | SynologyError::RemoteFileSystemUnreachable => libc::EHOSTUNREACH,
+            SynologyError::TimedOut => libc::ETIMEDOUT,
+            SynologyError::Http(401) | SynologyError::Http(403) => libc::EACCES,
+            SynologyError::Http(404) => libc::ENOENT,
+            SynologyError::NotLoggedIn
+                | SynologyError::PermissionDenied
+                | SynologyError::SessionTimeout
+                | SynologyError::SessionInterrupted
+                | SynologyError::SessionNotFound
+                | SynologyError::UploadNotAllowed
+                | SynologyError::InvalidUserOrGroup
+                | SynologyError::NotPermitted
+                | SynologyError::WrongPassword
+                | SynologyError::AccountDisabled
+                | SynologyError::LoginDenied
+                | SynologyError::OtpRequired
+                | SynologyError::OtpInvalid
+                | SynologyError::OtpEnforced
+                | SynologyError::PasswordExpired
+                | SynologyError::UntrustedCertificate => libc::EACCES,
+            SynologyError::DemoSite => libc::EPERM,
+            SynologyError::MissingParameter
+                | SynologyError::InvalidParameter
+                | SynologyError::IllegalName
+                | SynologyError::UnsupportedOnFat => libc::EINVAL,
+            SynologyError::NoSuchApi
+                | SynologyError::NoSuchMethod
+                | SynologyError::UnsupportedVersion => libc::ENOSYS,
+            SynologyError::SystemBusy | SynologyError::DeviceBusy => libc::EBUSY,
+            SynologyError::NotFound => libc::ENOENT,
+            SynologyError::UnsupportedFileSystem => libc::EOPNOTSUPP,
+            SynologyError::ReadOnlyFileSystem => libc::EROFS,
+            SynologyError::NameTooLong => libc::ENAMETOOLONG,
+            SynologyError::AlreadyExists | SynologyError::CannotOverwrite => libc::EEXIST,
+            SynologyError::FileFolderConflict => libc::EISDIR,
+            SynologyError::QuotaExceeded => libc::EDQUOT,
+            SynologyError::NoSpace => libc::ENOSPC,
+            SynologyError::TooManyFolders => libc::EMLINK,
+            SynologyError::Http(_)
+                | SynologyError::InvalidResponse
+                | SynologyError::Cache
+                | SynologyError::Unknown
+                | SynologyError::UploadFailed
+                | SynologyError::NetworkUnstable
+                | SynologyError::OperationFailed
+                | SynologyError::InputOutput
+                | SynologyError::NoSuchTask
+                | SynologyError::DeleteFailed
+                | SynologyError::CopyOrMoveFailed
+                | SynologyError::CreateFolderFailed
+                | SynologyError::RenameFailed
+                | SynologyError::Api(_) => libc::EIO,
+        }
+    }
+
+    #[cfg(target_family = "windows")]
+    pub fn to_ntstatus(self) -> i32 {
+        use winapi::shared::ntstatus;
+
+        match self {
+            SynologyError::Connection | SynologyError::RemoteFileSystemUnreachable => ntstatus::STATUS_HOST_UNREACHABLE,
+            SynologyError::TimedOut => ntstatus::STATUS_IO_TIMEOUT,
+            SynologyError::Http(401) | SynologyError::Http(403) => ntstatus::STATUS_ACCESS_DENIED,
+            SynologyError::Http(404) => ntstatus::STATUS_OBJECT_NAME_NOT_FOUND,
+            SynologyError::NotLoggedIn
+                | SynologyError::PermissionDenied
+                | SynologyError::SessionTimeout
+                | SynologyError::SessionInterrupted
+                | SynologyError::SessionNotFound
+                | SynologyError::UploadNotAllowed
+                | SynologyError::InvalidUserOrGroup
+                | SynologyError::NotPermitted
+                | SynologyError::DemoSite
+                | SynologyError::LoginDenied
+                | SynologyError::OtpRequired
+                | SynologyError::OtpInvalid
+                | SynologyError::OtpEnforced => ntstatus::STATUS_ACCESS_DENIED,
+            SynologyError::WrongPassword => ntstatus::STATUS_WRONG_PASSWORD,
+            SynologyError::AccountDisabled => ntstatus::STATUS_ACCOUNT_DISABLED,
+            SynologyError::PasswordExpired => ntstatus::STATUS_PASSWORD_EXPIRED,
+            SynologyError::UntrustedCertificate => ntstatus::STATUS_TRUST_FAILURE,
+            SynologyError::MissingParameter
+                | SynologyError::InvalidParameter
+                | SynologyError::UnsupportedOnFat => ntstatus::STATUS_INVALID_PARAMETER,
+            SynologyError::IllegalName => ntstatus::STATUS_OBJECT_NAME_INVALID,
+            SynologyError::NoSuchApi
+                | SynologyError::NoSuchMethod
+                | SynologyError::UnsupportedVersion => ntstatus::STATUS_NOT_IMPLEMENTED,
+            SynologyError::SystemBusy | SynologyError::DeviceBusy => ntstatus::STATUS_DEVICE_BUSY,
+            SynologyError::NotFound => ntstatus::STATUS_OBJECT_NAME_NOT_FOUND,
+            SynologyError::UnsupportedFileSystem => ntstatus::STATUS_NOT_SUPPORTED,
+            SynologyError::ReadOnlyFileSystem => ntstatus::STATUS_MEDIA_WRITE_PROTECTED,
+            SynologyError::NameTooLong => ntstatus::STATUS_NAME_TOO_LONG,
+            SynologyError::AlreadyExists | SynologyError::CannotOverwrite => ntstatus::STATUS_OBJECT_NAME_COLLISION,
+            SynologyError::FileFolderConflict => ntstatus::STATUS_FILE_IS_A_DIRECTORY,
+            SynologyError::QuotaExceeded => ntstatus::STATUS_QUOTA_EXCEEDED,
+            SynologyError::NoSpace => ntstatus::STATUS_DISK_FULL,
+            SynologyError::TooManyFolders => ntstatus::STATUS_TOO_MANY_LINKS,
+            SynologyError::Http(_)
+                | SynologyError::InvalidResponse
+                | SynologyError::Cache
+                | SynologyError::Unknown
+                | SynologyError::UploadFailed
+                | SynologyError::NetworkUnstable
+                | SynologyError::OperationFailed
+                | SynologyError::InputOutput
+                | SynologyError::NoSuchTask
+                | SynologyError::DeleteFailed
+                | SynologyError::CopyOrMoveFailed
+                | SynologyError::CreateFolderFailed
+                | SynologyError::RenameFailed
+                | SynologyError::Api(_) => ntstatus::STATUS_UNEXPECTED_IO_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for SynologyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SynologyError::Connection => write!(f, "the NAS could not be reached"),
+            SynologyError::TimedOut => write!(f, "the NAS did not answer in time"),
+            SynologyError::Http(status) => write!(f, "HTTP status {}", status),
+            SynologyError::InvalidResponse => write!(f, "the NAS sent an invalid response"),
+            SynologyError::NotLoggedIn => write!(f, "not logged in"),
+            SynologyError::UntrustedCertificate => write!(f, "the certificate of the NAS is not trusted"),
+            SynologyError::Cache => write!(f, "the local cache could not be accessed"),
+            SynologyError::NoSuchApi => write!(f, "the NAS does not offer an API this needs"),
+            SynologyError::UnsupportedVersion => write!(f, "the NAS does not offer a supported version of an API this needs"),
+            SynologyError::WrongPassword => write!(f, "no such account, or the password is wrong"),
+            SynologyError::AccountDisabled => write!(f, "the account is disabled"),
+            SynologyError::LoginDenied => write!(f, "the account may not log in from here"),
+            SynologyError::OtpRequired => write!(f, "a 2-step verification code is required"),
+            SynologyError::OtpInvalid => write!(f, "the 2-step verification code is wrong"),
+            SynologyError::OtpEnforced => write!(f, "2-step verification has to be set up for this account first"),
+            SynologyError::PasswordExpired => write!(f, "the password has expired"),
+            SynologyError::Api(code) => write!(f, "error code {}", code),
+            error => write!(f, "{:?}", error),
+        }
+    }
+}
+
+impl std::error::Error for SynologyError {}
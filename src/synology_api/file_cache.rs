@@ -1,16 +1,16 @@
-use std::{path::PathBuf, fs, time::SystemTime};
+use std::{path::PathBuf, fs, io::ErrorKind, time::SystemTime};
 
 use dirs::cache_dir;
 use log::{error, debug, info};
 
-use super::{file_station_filesystem::FileSystemInfo, epoch_from_seconds};
+use super::{file_station_filesystem::FileSystemInfo, inode_table::InodeTable, device_tokens::DeviceTokens, epoch_from_seconds, SynologyError};
 
 pub struct FileCache {
 	root: PathBuf,
 }
 
 impl FileCache {
-	pub fn new(hostname: &str) -> Result<FileCache, i32> {
+	pub fn new(hostname: &str) -> Result<FileCache, SynologyError> {
 		match cache_dir() {
 			Some(mut path) => {
 				path.push("Engineers for Exploration");
@@ -31,16 +31,24 @@ impl FileCache {
 					Err(err) => {
 						error!("An error occurred: {}", err);
 
-						Err(-1)
+						Err(SynologyError::Cache)
 					}
 				}
 
 
 			}
-			None => Err(-1)
+			None => Err(SynologyError::Cache)
 		}
 	}
 
+	pub fn get_inode_table(&self) -> Result<InodeTable, SynologyError> {
+		InodeTable::new(&self.get_sqlite_path())
+	}
+
+	pub fn get_device_tokens(&self) -> Result<DeviceTokens, SynologyError> {
+		DeviceTokens::new(&self.get_sqlite_path())
+	}
+
 	pub fn is_file_cached(&self, info: &FileSystemInfo) -> bool {
 		let query = "SELECT mtime FROM cached_files WHERE path = ?";
 		let mtime_result = match self.get_sqlite_connection() {
@@ -60,9 +68,9 @@ impl FileCache {
 			}
 		};
 
-		let cache_path = self.get_cache_path(info);
+		let cache_path = self.get_cache_path(&info.path);
 		if !cache_path.exists() {
-			self.delete_cache_entry(info).unwrap();
+			self.delete_cache_entry(&info.path).unwrap();
 			return false;
 		}
 
@@ -72,7 +80,7 @@ impl FileCache {
 					return true;
 				}
 
-				self.delete_cache_entry(info).unwrap();
+				self.delete_cache_entry(&info.path).unwrap();
 				false
 			},
 			None => false
@@ -82,17 +90,17 @@ impl FileCache {
 	pub fn get_file_cache(&self, info: &FileSystemInfo) -> Option<fs::File> {
 		if self.is_file_cached(info) {
 			self.update_cache_access_time(info).unwrap();
-			let file = fs::File::open(self.get_cache_path(info)).unwrap();
+			let file = fs::File::open(self.get_cache_path(&info.path)).unwrap();
 			return Some(file);
 		}
 
-		return Default::default();
+		Default::default()
 	}
 
-	pub fn create_file_cache(&self, info: &FileSystemInfo) -> Result<fs::File, i32> {
+	pub fn create_file_cache(&self, info: &FileSystemInfo) -> Result<fs::File, SynologyError> {
 		match self.get_sqlite_connection() {
 			Ok(connection) => {
-				match fs::File::create(self.get_cache_path(info)) {
+				match fs::File::create(self.get_cache_path(&info.path)) {
 					Ok(file) => {
 						let query = "INSERT INTO cached_files VALUES (?, ?, ?, ?)";
 						connection
@@ -114,19 +122,19 @@ impl FileCache {
 					Err(error) => {
 						error!("An error occurred: {}", error);
 
-						Err(-1)
+						Err(SynologyError::Cache)
 					}
 				}
 			},
 			Err(error) => {
 				error!("An error occurred: {}", error);
 
-				Err(-1)
+				Err(SynologyError::Cache)
 			}
 		}
 	}
 
-	fn update_cache_access_time(&self, info: &FileSystemInfo) -> Result<(), i32> {
+	fn update_cache_access_time(&self, info: &FileSystemInfo) -> Result<(), SynologyError> {
 		match self.get_sqlite_connection() {
 			Ok(connection) => {
 				let update_query = "
@@ -149,15 +157,20 @@ impl FileCache {
 			Err(error) => {
 				error!("An error occurred while updating the file cache access time: {}", error);
 
-				Err(-1)
+				Err(SynologyError::Cache)
 			}
 		}
 	}
 
-	fn delete_cache_entry(&self, info: &FileSystemInfo) -> Result<(), i32> {
+	/// Drops the cached copy of `path`, e.g. because it changed on the NAS.
+	pub fn remove_file_cache(&self, path: &str) -> Result<(), SynologyError> {
+		self.delete_cache_entry(path)
+	}
+
+	fn delete_cache_entry(&self, file_path: &str) -> Result<(), SynologyError> {
 		match self.get_sqlite_connection() {
 			Ok(connection) => {
-				let path = self.get_cache_path(info);
+				let path = self.get_cache_path(file_path);
 
 				// Remove the invalid cache entry.
 				let delete_query = "DELETE FROM cached_files WHERE path = ?";
@@ -165,31 +178,33 @@ impl FileCache {
 					.prepare(delete_query)
 					.unwrap()
 					.into_iter()
-					.bind((1, info.path.as_str()))
+					.bind((1, file_path))
 					.unwrap()
 					.next();
 
 				match fs::remove_file(path) {
 					Ok(()) => Ok(()),
+					// Nothing was downloaded yet.
+					Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
 					Err(error) => {
 						error!("An error occurred while deleting the file cache: {}", error);
 		
-						Err(-1)
+						Err(SynologyError::Cache)
 					}
 				}
 			},
 			Err(error) => {
 				error!("An error occurred while deleting the file cache: {}", error);
 
-				Err(-1)
+				Err(SynologyError::Cache)
 			}
 		}
 	}
 
-	fn get_cache_path(&self, info: &FileSystemInfo) -> PathBuf {
+	fn get_cache_path(&self, file_path: &str) -> PathBuf {
 		let mut path = self.root.clone();
 
-		for part in info.path.split("/").into_iter() {
+		for part in file_path.split('/') {
 			path.push(part);
 		}
 		fs::create_dir_all(path.parent().unwrap()).unwrap();
@@ -197,11 +212,15 @@ impl FileCache {
 		path
 	}
 
-	fn get_sqlite_connection(&self) -> Result<sqlite::Connection, sqlite::Error> {
+	fn get_sqlite_path(&self) -> PathBuf {
 		let mut db_path = self.root.clone();
 		db_path.push("cache.db");
 
-		sqlite::open(db_path)
+		db_path
+	}
+
+	fn get_sqlite_connection(&self) -> Result<sqlite::Connection, sqlite::Error> {
+		sqlite::open(self.get_sqlite_path())
 	}
 
 	fn get_sqlite_version(&self, connection: &sqlite::Connection) -> u8 {
@@ -233,17 +252,51 @@ impl FileCache {
 			.next().unwrap()
 	}
 
-	fn init_sqlite(&self) -> Result<(), i32> {
+	/// Creates the database readable by the current user alone, as it holds the device tokens that
+	/// let this computer skip 2-step verification.  A database from an older version is restricted too.
+	#[cfg(target_family = "unix")]
+	fn restrict_sqlite_permissions(&self) -> Result<(), SynologyError> {
+		use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+		let db_path = self.get_sqlite_path();
+		let result = fs::OpenOptions::new()
+			.write(true)
+			.create(true)
+			.mode(0o600)
+			.open(&db_path)
+			.and_then(|_| fs::set_permissions(&db_path, fs::Permissions::from_mode(0o600)));
+
+		match result {
+			Ok(()) => Ok(()),
+			Err(error) => {
+				error!("An error occurred while restricting access to the cache database: {}", error);
+
+				Err(SynologyError::Cache)
+			}
+		}
+	}
+
+	#[cfg(target_family = "windows")]
+	fn restrict_sqlite_permissions(&self) -> Result<(), SynologyError> {
+		// The cache lives in the user's profile, which other users cannot read.
+		Ok(())
+	}
+
+	fn init_sqlite(&self) -> Result<(), SynologyError> {
+		self.restrict_sqlite_permissions()?;
+
 		match self.get_sqlite_connection() {
 			Ok(connection) => {
 				let version: u8 = self.get_sqlite_version(&connection);
 				info!("Current database version is {}.", version);
 
-				self.init_sqlite_v1(&connection, version);
+				let version = self.init_sqlite_v1(&connection, version);
+				let version = self.init_sqlite_v2(&connection, version);
+				self.init_sqlite_v3(&connection, version);
 
 				Ok(())
 			},
-			Err(_err) => Err(-1)
+			Err(_err) => Err(SynologyError::Cache)
 		}
 	}
 
@@ -259,6 +312,34 @@ impl FileCache {
 		self.set_sqlite_version(connection, 1)
 	}
 
+	fn init_sqlite_v2(&self, connection: &sqlite::Connection, current_version: u8) -> u8 {
+		if current_version >= 2 {
+			// We don't need to perform this upgrade.
+			return current_version;
+		}
+
+		// The root always has to be inode 1 for FUSE.
+		let query = "
+			CREATE TABLE inodes (ino INTEGER PRIMARY KEY AUTOINCREMENT, path TEXT NOT NULL UNIQUE);
+			INSERT INTO inodes (ino, path) VALUES (1, '/');
+		";
+		connection.execute(query).unwrap();
+
+		self.set_sqlite_version(connection, 2)
+	}
+
+	fn init_sqlite_v3(&self, connection: &sqlite::Connection, current_version: u8) -> u8 {
+		if current_version >= 3 {
+			// We don't need to perform this upgrade.
+			return current_version;
+		}
+
+		let query = "CREATE TABLE device_tokens (account TEXT PRIMARY KEY, device_id TEXT NOT NULL);";
+		connection.execute(query).unwrap();
+
+		self.set_sqlite_version(connection, 3)
+	}
+
 	fn set_sqlite_version(&self, connection: &sqlite::Connection, version: u8) -> u8 {
 		let query = "
 			UPDATE property_bag
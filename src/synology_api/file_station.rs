@@ -1,302 +1,886 @@
-use std::{collections::HashMap, sync::Mutex, time::{Duration, SystemTime}};
+use std::{collections::HashMap, sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant}};
 use bytes::Bytes;
-use serde::de::DeserializeOwned;
-use urlencoding::encode;
-use log::{debug, error};
+use serde::{Deserialize, de::DeserializeOwned};
+use log::{debug, error, info};
+use reqwest::{Client, header::CONTENT_TYPE};
 
-use crate::synology_api::responses::{SynologyResult, LoginResult, ListSharesResult, ListFilesResult};
+use crate::options::Options;
 
-use super::responses::{FileStationItem, FileAdditional};
+use crate::synology_api::responses::{SynologyResult, LoginResult, ListSharesResult, ListFilesResult, CreateFolderResult, BackgroundTaskResult, Md5StatusResult, SearchListResult};
 
+use super::SynologyError;
+use super::api_info::{ApiCatalog, ApiEndpoint};
+use super::request::ApiRequest;
+use super::response_cache::ResponseCache;
+use super::retry::RetryPolicy;
+use super::search::SearchQuery;
+use super::tls::{check_pinned_certificate, configure_tls};
+use super::responses::{ApiInfo, FileStationItem, FileAdditional};
 
-struct FileStationCacheItem {
-    cache_time: SystemTime,
-    data: serde_json::Value
-}
+
+/// How many bytes of responses are kept around at most.
+const CACHE_SIZE: usize = 8 * 1024 * 1024;
+
+/// What is asked for about every file and folder.
+const FILE_ADDITIONAL: [&str; 5] = ["real_path", "size", "owner", "time", "perm"];
+
+/// What is asked for about every share.
+const SHARE_ADDITIONAL: [&str; 5] = ["real_path", "owner", "volume_status", "time", "perm"];
+
+/// Number of search matches fetched from the NAS at a time.
+const SEARCH_PAGE_SIZE: usize = 1000;
+
+/// Upper bound on the number of matches a search returns, however many the NAS found.
+const SEARCH_MATCH_LIMIT: usize = 10000;
+
+/// How this client shows up in the list of trusted devices on the NAS.
+const DEVICE_NAME: &str = "synology-filestation-fuse";
+
+/// Requests that only read, and can therefore be sent again when they fail along the way.
+const IDEMPOTENT_REQUESTS: [(&str, &str); 6] = [
+    ("SYNO.FileStation.List", "list"),
+    ("SYNO.FileStation.List", "list_share"),
+    ("SYNO.FileStation.List", "getinfo"),
+    ("SYNO.FileStation.MD5", "start"),
+    ("SYNO.FileStation.MD5", "status"),
+    ("SYNO.FileStation.Search", "list"),
+];
 
 pub struct FileStation {
     pub hostname: String,
     pub base_url: String,
 
-    cache: Mutex<HashMap<String, FileStationCacheItem>>,
-    cache_lifetime: Duration,
-    sid: Option<String>,
+    cache: ResponseCache,
+    sid: Mutex<Option<String>>,
+    credentials: Mutex<Option<Arc<Credentials>>>,
+    renewal: tokio::sync::Mutex<()>,
+    api_catalog: Mutex<Option<Arc<ApiCatalog>>>,
+
+    client: Client,
+    /// Fingerprint every connection's certificate has to match, when the certificate is pinned.
+    fingerprint: Option<String>,
+    request_timeout: Duration,
+    retry_policy: RetryPolicy,
+}
+
+/// What it takes to log in again once the session expires.
+struct Credentials {
+    username: String,
+    password: String,
+    device_id: Option<String>,
 }
 
 impl FileStation {
-    pub fn new(hostname: &str, port: u16, secured: bool, cache_lifetime: Duration) -> Self {
+    pub fn new(hostname: &str, port: u16, secured: bool, options: &Options) -> Result<Self, SynologyError> {
         let protocol = if secured { "https" } else { "http" };
         let base_url = format!("{}://{}:{}", protocol, hostname, port);
 
-        FileStation {
+        // One client for every call, so connections and TLS sessions are reused.
+        let mut builder = Client::builder()
+            .connect_timeout(options.connect_timeout)
+            .pool_idle_timeout(options.idle_timeout)
+            .tcp_keepalive(Duration::from_secs(60));
+        if options.http2 {
+            builder = builder.http2_prior_knowledge();
+        }
+        if secured {
+            builder = configure_tls(builder, hostname, options)?;
+        }
+
+        let client = match builder.build() {
+            Ok(client) => client,
+            Err(error) => {
+                error!("Could not set up the HTTP client: {}", error);
+
+                return Err(SynologyError::UntrustedCertificate);
+            }
+        };
+
+        Ok(FileStation {
             hostname: hostname.to_string(),
             base_url: base_url.to_string(),
-            cache: Mutex::new(HashMap::new()),
-            cache_lifetime,
-            sid: Default::default()
+            cache: ResponseCache::new(CACHE_SIZE),
+            sid: Mutex::new(None),
+            credentials: Mutex::new(None),
+            renewal: tokio::sync::Mutex::new(()),
+            api_catalog: Mutex::new(None),
+            client,
+            fingerprint: if secured { options.fingerprint.clone() } else { None },
+            request_timeout: options.request_timeout,
+            retry_policy: RetryPolicy::new(options.retry_attempts, options.retry_delay),
+        })
+    }
+
+    /// Refuses a response that came over a connection to anyone but the holder of the pinned certificate.
+    fn check_certificate(&self, response: &reqwest::Response) -> Result<(), SynologyError> {
+        match &self.fingerprint {
+            Some(fingerprint) => check_pinned_certificate(response, fingerprint),
+            None => Ok(())
         }
     }
 
-    pub async fn download(&self, path: &str, callback: impl Fn(Bytes) -> Result<(), i32>) -> Result<(), i32> {
-        match &self.sid {
-            Some(sid) => {
-                let download_url = format!(
-                    "{}/webapi/entry.cgi?api={}&version={}&method={}&path={}&mode={}&_sid={}",
-                    self.base_url,
-                    "SYNO.FileStation.Download",
-                    2,
-                    "download",
-                    path,
-                    "download",
-                    sid);
-                let result = reqwest::get(download_url).await;
-
-                match result {
-                    Ok(mut res) => {
-                        if res.status() != 200 {
-                            error!("An errror occurred while downloading: {}", res.status());
-
-                            return Err(res.status().as_u16() as i32);
-                        }
+    fn current_sid(&self) -> Result<String, SynologyError> {
+        match self.sid.lock().unwrap().as_ref() {
+            Some(sid) => Ok(sid.clone()),
+            None => Err(SynologyError::NotLoggedIn)
+        }
+    }
 
-                        loop {
-                            let result = match res.chunk().await {
-                                Ok(result) => match result {
-                                    Some(bytes) => {
-                                        match callback(bytes) {
-                                            Ok(()) => Ok(false),
-                                            Err(error) => Err(error)
-                                        }
-                                    }
-                                    None => Ok(true)
-                                },
-                                Err(error) => {
-                                    error!("An error occurred while downloading chunks: {}", error);
-                                    Err(-1)
-                                }
-                            };
+    /// Logs in again after `expired_sid` ran out, and returns the new session.  Only one thread logs in,
+    /// the others waiting on the renewal lock find the new session once it is their turn.
+    async fn renew_session(&self, expired_sid: &str) -> Result<String, SynologyError> {
+        let _renewal = self.renewal.lock().await;
 
-                            if result.is_err() {
-                                return Err(result.err().unwrap());
-                            } else if result.unwrap() {
-                                break;
-                            }
-                        }
+        let sid = self.current_sid()?;
+        if sid != expired_sid {
+            return Ok(sid);
+        }
 
-                        Ok(())
+        let credentials = match self.credentials.lock().unwrap().clone() {
+            Some(credentials) => credentials,
+            None => return Err(SynologyError::NotLoggedIn)
+        };
+
+        info!("The session expired, logging in again.");
+        self.login(&credentials.username, &credentials.password, None, credentials.device_id.as_deref()).await?;
+
+        self.current_sid()
+    }
+
+    fn endpoint(&self, api: &str) -> Result<ApiEndpoint, SynologyError> {
+        match self.api_catalog.lock().unwrap().as_ref() {
+            Some(catalog) => catalog.endpoint(api),
+            None => Err(SynologyError::NotLoggedIn)
+        }
+    }
+
+    /// Asks the NAS which APIs it offers, where, and in which versions.  Only done once, since that
+    /// does not change while mounted.
+    async fn discover_apis(&self) -> Result<Arc<ApiCatalog>, SynologyError> {
+        if let Some(catalog) = self.api_catalog.lock().unwrap().as_ref() {
+            return Ok(catalog.clone());
+        }
+
+        let apis = self.retry_policy.run("SYNO.API.Info.query", || self.query_api_info()).await?;
+        let catalog = Arc::new(ApiCatalog::new(apis));
+        *self.api_catalog.lock().unwrap() = Some(catalog.clone());
+
+        Ok(catalog)
+    }
+
+    async fn query_api_info(&self) -> Result<HashMap<String, ApiInfo>, SynologyError> {
+        let endpoint = ApiEndpoint {
+            path: "query.cgi".to_string(),
+            version: 1,
+        };
+        let query_url = ApiRequest::new("SYNO.API.Info", "query")
+            .param("query", "SYNO.API.Auth,SYNO.FileStation.")
+            .url(&self.base_url, &endpoint, None);
+        let result = self.client.get(query_url).timeout(self.request_timeout).send().await;
+
+        match result {
+            Ok(res) => {
+                self.check_certificate(&res)?;
+
+                if res.status() != 200 {
+                    error!("An error occurred while querying the APIs of the NAS: {}", res.status());
+
+                    return Err(SynologyError::Http(res.status().as_u16()));
+                }
+
+                let value = match res.json::<serde_json::Value>().await {
+                    Ok(value) => value,
+                    Err(error) => {
+                        error!("An error occurred when reading the API info: {}", error);
+
+                        return Err(SynologyError::InvalidResponse);
+                    }
+                };
+
+                if value["success"].as_bool() != Some(true) {
+                    return Err(match value["error"]["code"].as_i64() {
+                        Some(code) => SynologyError::from_code(code as i32),
+                        None => SynologyError::InvalidResponse
+                    });
+                }
+
+                match serde_json::from_value::<SynologyResult<HashMap<String, ApiInfo>>>(value) {
+                    Ok(info) => {
+                        debug!("The NAS offers: {:?}", info.data);
+
+                        Ok(info.data)
                     },
-                    Err(_err) => Err(-1)
+                    Err(error) => {
+                        error!("err: {} with API info json.", error);
+
+                        Err(SynologyError::InvalidResponse)
+                    }
                 }
             },
-            None => Err(403)
+            Err(error) => {
+                error!("An error occurred while querying the APIs of the NAS: {}", error);
+
+                Err(Self::request_error(error))
+            }
         }
     }
 
-    pub async fn get_info_for_path(&self, path: &str) -> Result<FileStationItem<FileAdditional>, i32> {
-        match self.get_info_for_paths(vec!(path)).await {
-            Ok(result) => Ok(result.files.first().unwrap().clone()),
-            Err(error) => Err(error)
+    fn request_error(error: reqwest::Error) -> SynologyError {
+        if error.is_timeout() {
+            SynologyError::TimedOut
+        } else {
+            match error.status() {
+                Some(status) => SynologyError::Http(status.as_u16()),
+                None => SynologyError::Connection
+            }
         }
     }
 
-    pub async fn get_info_for_paths(&self, paths: Vec<&str>) -> Result<ListFilesResult, i32> {
-        let mut paths_str = "".to_string();
-        paths_str += paths.first().unwrap();
+    pub async fn download(&self, path: &str, callback: impl Fn(Bytes) -> Result<(), SynologyError>) -> Result<(), SynologyError> {
+        // A retried download starts over, so skip whatever the callback was already given.
+        let delivered = AtomicU64::new(0);
 
-        if paths_str.len() > 1 {
-            for file in paths.iter().skip(1) {
-                paths_str += format!(",{}", file).as_ref();
-            }
+        self.retry_policy.run(path, || self.download_once(path, &callback, &delivered)).await
+    }
+
+    async fn download_once(&self, path: &str, callback: &impl Fn(Bytes) -> Result<(), SynologyError>, delivered: &AtomicU64) -> Result<(), SynologyError> {
+        let sid = self.current_sid()?;
+
+        match self.download_with_sid(&sid, path, callback, delivered).await {
+            Err(error) if error.is_session_error() => {
+                let sid = self.renew_session(&sid).await?;
+
+                self.download_with_sid(&sid, path, callback, delivered).await
+            },
+            result => result
         }
+    }
 
-        let mut additional = HashMap::new();
+    async fn download_with_sid(&self, sid: &str, path: &str, callback: &impl Fn(Bytes) -> Result<(), SynologyError>, delivered: &AtomicU64) -> Result<(), SynologyError> {
+        let endpoint = self.endpoint("SYNO.FileStation.Download")?;
+        let download_url = Self::download_request(path).url(&self.base_url, &endpoint, Some(sid));
+        // Downloads may take any time, as long as the chunks keep coming.
+        let result = match tokio::time::timeout(self.request_timeout, self.client.get(download_url).send()).await {
+            Ok(result) => result,
+            Err(_elapsed) => {
+                error!("The download of {} did not start in time.", path);
 
-        let encoded_path = encode(paths_str.as_str()).to_string();
-        additional.insert("path", encoded_path.as_str());
-        
-        let encoded_additional = encode("[\"size\",\"time\",\"perm\"]").to_string();
-        additional.insert("additional", encoded_additional.as_str());
+                return Err(SynologyError::TimedOut);
+            }
+        };
 
-        let result: Result<serde_json::Value, i32> = self.get("SYNO.FileStation.List", 2, "getinfo", &additional, true).await;
         match result {
-            Ok(value) => {
-                for item in value["files"].as_array().unwrap().iter() {
-                    if item["code"].is_number() {
-                        return Err(item["code"].as_i64().unwrap() as i32);
+            Ok(mut res) => {
+                self.check_certificate(&res)?;
+
+                if res.status() != 200 {
+                    error!("An errror occurred while downloading: {}", res.status());
+
+                    return Err(SynologyError::Http(res.status().as_u16()));
+                }
+
+                // Errors, such as an expired session, come back as JSON instead of the file.
+                let is_json = res
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|content_type| content_type.to_str().ok())
+                    .map_or(false, |content_type| content_type.starts_with("application/json"));
+                if is_json {
+                    let bytes = match res.bytes().await {
+                        Ok(bytes) => bytes,
+                        Err(error) => return Err(Self::request_error(error))
+                    };
+
+                    if let Ok(value) = serde_json::from_slice::<serde_json::Value>(&bytes) {
+                        if value["success"].as_bool() == Some(false) {
+                            error!("The download of {} failed with json '{}'.", path, value);
+
+                            return Err(match value["error"]["code"].as_i64() {
+                                Some(code) => SynologyError::from_code(code as i32),
+                                None => SynologyError::InvalidResponse
+                            });
+                        }
                     }
+
+                    // A JSON file comes in one piece, of which a failed attempt may have delivered the start already.
+                    let already_delivered = delivered.load(Ordering::SeqCst);
+                    if bytes.len() as u64 <= already_delivered {
+                        return Ok(());
+                    }
+
+                    delivered.store(bytes.len() as u64, Ordering::SeqCst);
+                    return callback(bytes.slice(already_delivered as usize..));
                 }
 
-                let value_str = value.to_string();
-                let parsed_result = serde_json::from_value::<ListFilesResult>(value);
+                let mut position: u64 = 0;
+                loop {
+                    let chunk = match tokio::time::timeout(self.request_timeout, res.chunk()).await {
+                        Ok(chunk) => chunk,
+                        Err(_elapsed) => {
+                            error!("The download of {} stalled.", path);
 
-                return match parsed_result {
-                    Ok(parsed) => Ok(parsed),
-                    Err(error) => {
-                        error!("Error: {} with json: {}", error, value_str);
+                            return Err(SynologyError::TimedOut);
+                        }
+                    };
+
+                    let result = match chunk {
+                        Ok(result) => match result {
+                            Some(mut bytes) => {
+                                let start = position;
+                                position += bytes.len() as u64;
+
+                                let already_delivered = delivered.load(Ordering::SeqCst);
+                                if position <= already_delivered {
+                                    Ok(false)
+                                } else {
+                                    if start < already_delivered {
+                                        bytes = bytes.slice((already_delivered - start) as usize..);
+                                    }
+                                    delivered.store(position, Ordering::SeqCst);
 
-                        Err(-4)
+                                    match callback(bytes) {
+                                        Ok(()) => Ok(false),
+                                        Err(error) => Err(error)
+                                    }
+                                }
+                            }
+                            None => Ok(true)
+                        },
+                        Err(error) => {
+                            error!("An error occurred while downloading chunks: {}", error);
+                            Err(Self::request_error(error))
+                        }
+                    };
+
+                    if result.is_err() {
+                        return Err(result.err().unwrap());
+                    } else if result.unwrap() {
+                        break;
                     }
                 }
+
+                Ok(())
+            },
+            Err(error) => Err(Self::request_error(error))
+        }
+    }
+
+    /// Fetches the information of several paths in one call.  Each path gets its own result,
+    /// in the order the paths were given, so a missing file does not fail the others.
+    pub async fn get_info_for_paths(&self, paths: Vec<&str>) -> Result<Vec<Result<FileStationItem<FileAdditional>, SynologyError>>, SynologyError> {
+        let request = ApiRequest::new("SYNO.FileStation.List", "getinfo")
+            .paths("path", &paths)
+            .list("additional", &FILE_ADDITIONAL);
+
+        let result: Result<serde_json::Value, SynologyError> = self.get(&request).await;
+        match result {
+            Ok(value) => {
+                let items = match value["files"].as_array() {
+                    Some(items) => items,
+                    None => {
+                        error!("No files in getinfo json: {}", value);
+
+                        return Err(SynologyError::InvalidResponse);
+                    }
+                };
+
+                if items.len() != paths.len() {
+                    error!("Asked for {} paths but got {} with json: {}", paths.len(), items.len(), value);
+
+                    return Err(SynologyError::InvalidResponse);
+                }
+
+                let files = items.iter().map(|item| {
+                    if item["code"].is_number() {
+                        return Err(SynologyError::from_code(item["code"].as_i64().unwrap() as i32));
+                    }
+
+                    match serde_json::from_value::<FileStationItem<FileAdditional>>(item.clone()) {
+                        Ok(parsed) => Ok(parsed),
+                        Err(error) => {
+                            error!("Error: {} with json: {}", error, item);
+
+                            Err(SynologyError::InvalidResponse)
+                        }
+                    }
+                }).collect();
+
+                Ok(files)
             },
             Err(error) => Err(error)
         }
     }
 
-    pub async fn list_files(&self, path: &str) -> Result<ListFilesResult, i32> {
-        let mut additional = HashMap::new();
+    /// Lists `limit` entries of the folder starting at `offset`.  A limit of 0 lists everything.
+    pub async fn list_files(&self, path: &str, offset: u64, limit: u64) -> Result<ListFilesResult, SynologyError> {
+        let request = ApiRequest::new("SYNO.FileStation.List", "list")
+            .path("folder_path", path)
+            .param("offset", offset)
+            .param("limit", limit)
+            .list("additional", &FILE_ADDITIONAL);
 
-        let encoded_path = encode(path).to_string();
-        additional.insert("folder_path", encoded_path.as_str());
+        self.get(&request).await
+    }
 
-        
-        let encoded_additional = encode("[\"size\",\"time\",\"perm\"]").to_string();
-        additional.insert("additional", encoded_additional.as_str());
+    pub async fn list_shares(&self) -> Result<ListSharesResult, SynologyError> {
+        let request = ApiRequest::new("SYNO.FileStation.List", "list_share")
+            .list("additional", &SHARE_ADDITIONAL);
 
-        self.get("SYNO.FileStation.List", 2, "list", &additional, true).await
+        self.get(&request).await
     }
 
-    pub async fn list_shares(&self) -> Result<ListSharesResult, i32> {
-        let mut additional = HashMap::new();
+    pub async fn check_permission(&self, folder_path: &str, filename: &str, overwrite: bool) -> Result<(), SynologyError> {
+        let request = ApiRequest::new("SYNO.FileStation.CheckPermission", "write")
+            .path("path", folder_path)
+            .param("filename", filename)
+            .param("overwrite", overwrite)
+            .param("create_only", !overwrite);
 
-        let encoded_additional = encode("[\"volume_status\",\"time\",\"perm\"]").to_string();
-        additional.insert("additional", encoded_additional.as_str());
+        let result: Result<serde_json::Value, SynologyError> = self.get(&request).await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(error) => Err(error)
+        }
+    }
+
+    // DSM splits the paths and names of these calls on commas unless they come as JSON arrays, so a
+    // single value is sent as an array of one.
+
+    fn download_request(path: &str) -> ApiRequest {
+        ApiRequest::new("SYNO.FileStation.Download", "download")
+            .paths("path", &[path])
+            .param("mode", "download")
+    }
 
-        self.get("SYNO.FileStation.List", 2, "list_share", &additional, true).await
+    fn create_folder_request(folder_path: &str, name: &str) -> ApiRequest {
+        ApiRequest::new("SYNO.FileStation.CreateFolder", "create")
+            .paths("folder_path", &[folder_path])
+            .list("name", &[name])
+            .param("force_parent", false)
+            .list("additional", &FILE_ADDITIONAL)
     }
 
-    pub async fn login(&mut self, username: &str, password: &str) -> Result<(), i32> {
-        let login_url = format!(
-            "{}/webapi/auth.cgi?api=SYNO.API.Auth&version={}&method=login&account={}&passwd={}&session=FileStation&format=sid",
-            self.base_url,
-            3,
-            username,
-            password);
-        let result = reqwest::get(login_url).await;
+    fn rename_request(path: &str, name: &str) -> ApiRequest {
+        ApiRequest::new("SYNO.FileStation.Rename", "rename")
+            .paths("path", &[path])
+            .list("name", &[name])
+    }
+
+    pub async fn create_folder(&self, folder_path: &str, name: &str) -> Result<CreateFolderResult, SynologyError> {
+        let request = Self::create_folder_request(folder_path, name);
+
+        let result = self.get(&request).await;
+        self.cache.invalidate_path(&format!("{}/{}", folder_path.trim_end_matches('/'), name));
+
+        result
+    }
+
+    pub async fn rename(&self, path: &str, name: &str) -> Result<ListFilesResult, SynologyError> {
+        let request = Self::rename_request(path, name);
+
+        let result = self.get(&request).await;
+
+        // The new name sits in the same folder, which is forgotten along with the old name.
+        self.cache.invalidate_path(path);
+        if let Some(index) = path.rfind('/') {
+            self.cache.invalidate_path(&format!("{}/{}", &path[..index], name));
+        }
+
+        result
+    }
+
+    /// Drops the cached responses about `path`, e.g. because it changed on the NAS.
+    pub fn invalidate_path(&self, path: &str) {
+        self.cache.invalidate_path(path);
+    }
+
+    /// Has the NAS compute the MD5 of a file, waiting for the background task to finish.
+    pub async fn md5(&self, path: &str) -> Result<String, SynologyError> {
+        let request = ApiRequest::new("SYNO.FileStation.MD5", "start").path("file_path", path);
+
+        let task: BackgroundTaskResult = match self.get(&request).await {
+            Ok(task) => task,
+            Err(error) => return Err(error)
+        };
+
+        let status_request = ApiRequest::new("SYNO.FileStation.MD5", "status").param("taskid", &task.taskid);
+        let stop_request = ApiRequest::new("SYNO.FileStation.MD5", "stop").param("taskid", &task.taskid);
+        let deadline = Instant::now() + self.request_timeout;
+
+        loop {
+            let status: Result<Md5StatusResult, SynologyError> = match self.get::<Md5StatusResult>(&status_request).await {
+                Ok(status) if Instant::now() >= deadline && !status.finished => {
+                    error!("The MD5 task for {} did not finish within {:?}.", path, self.request_timeout);
+
+                    Err(SynologyError::TimedOut)
+                },
+                status => status
+            };
+            match status {
+                Ok(status) if status.finished => {
+                    return match status.md5 {
+                        Some(md5) => Ok(md5),
+                        None => {
+                            error!("The MD5 task for {} finished without a result.", path);
+
+                            Err(SynologyError::InvalidResponse)
+                        }
+                    };
+                },
+                Ok(_) => tokio::time::sleep(Duration::from_millis(200)).await,
+                Err(error) => {
+                    let stopped: Result<serde_json::Value, SynologyError> = self.get(&stop_request).await;
+                    if let Err(stop_error) = stopped {
+                        error!("An error occurred while stopping the MD5 task for {}: {}", path, stop_error);
+                    }
+
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// Searches the folders of `query` on the NAS, waiting for the background task to finish for as
+    /// long as a request may take, and returns the matches, up to `SEARCH_MATCH_LIMIT` of them.
+    pub async fn search(&self, query: &SearchQuery) -> Result<Vec<FileStationItem<FileAdditional>>, SynologyError> {
+        let folders: Vec<&str> = query.folders.iter().map(|folder| folder.as_str()).collect();
+        let mut request = ApiRequest::new("SYNO.FileStation.Search", "start")
+            .paths("folder_path", &folders)
+            .param("recursive", true);
+        if let Some(pattern) = &query.pattern {
+            request = request.param("pattern", pattern);
+        }
+        if let Some(extension) = &query.extension {
+            request = request.param("extension", extension);
+        }
+        if let Some(size_from) = query.size_from {
+            request = request.param("size_from", size_from);
+        }
+        if let Some(size_to) = query.size_to {
+            request = request.param("size_to", size_to);
+        }
+        if let Some(modified_after) = query.modified_after {
+            request = request.param("mtime_from", modified_after);
+        }
+        if let Some(modified_before) = query.modified_before {
+            request = request.param("mtime_to", modified_before);
+        }
+
+        let task: BackgroundTaskResult = match self.get(&request).await {
+            Ok(task) => task,
+            Err(error) => return Err(error)
+        };
+
+        // Only whether the task is done matters until it is, so the matches are fetched once, at the end.
+        let status_request = ApiRequest::new("SYNO.FileStation.Search", "list")
+            .param("taskid", &task.taskid)
+            .param("offset", 0)
+            .param("limit", 1);
+        let deadline = Instant::now() + self.request_timeout;
+
+        let result = loop {
+            let status: Result<SearchListResult, SynologyError> = self.get(&status_request).await;
+            match status {
+                Ok(status) if status.finished => break self.list_search_matches(&task.taskid).await,
+                Ok(_) if Instant::now() >= deadline => {
+                    error!("The search task {} did not finish within {:?}.", task.taskid, self.request_timeout);
+
+                    break Err(SynologyError::TimedOut);
+                },
+                Ok(_) => tokio::time::sleep(Duration::from_millis(200)).await,
+                Err(error) => break Err(error)
+            }
+        };
+
+        // The NAS keeps the matches until told otherwise, and keeps searching if this gave up early.
+        for method in ["stop", "clean"] {
+            let cleanup_request = ApiRequest::new("SYNO.FileStation.Search", method).param("taskid", &task.taskid);
+            let cleaned: Result<serde_json::Value, SynologyError> = self.get(&cleanup_request).await;
+            if let Err(error) = cleaned {
+                error!("An error occurred while cleaning up the search task {}: {}", task.taskid, error);
+            }
+        }
+
+        result
+    }
+
+    /// Fetches the matches of a finished search task, a page at a time.
+    async fn list_search_matches(&self, taskid: &str) -> Result<Vec<FileStationItem<FileAdditional>>, SynologyError> {
+        let mut matches: Vec<FileStationItem<FileAdditional>> = Vec::new();
+
+        loop {
+            let list_request = ApiRequest::new("SYNO.FileStation.Search", "list")
+                .param("taskid", taskid)
+                .param("offset", matches.len())
+                .param("limit", SEARCH_PAGE_SIZE)
+                .list("additional", &FILE_ADDITIONAL);
+
+            let page: SearchListResult = match self.get(&list_request).await {
+                Ok(page) => page,
+                Err(error) => return Err(error)
+            };
+
+            let count = page.files.len();
+            let total = page.total.map(|total| total.max(0) as usize);
+            matches.extend(page.files);
+
+            if matches.len() >= SEARCH_MATCH_LIMIT {
+                info!("The search task {} found more than {} matches, the rest are left out.", taskid, SEARCH_MATCH_LIMIT);
+                matches.truncate(SEARCH_MATCH_LIMIT);
+
+                return Ok(matches);
+            }
+
+            if count == 0 || total.map_or(count < SEARCH_PAGE_SIZE, |total| matches.len() >= total) {
+                return Ok(matches);
+            }
+        }
+    }
+
+    /// Logs in, and returns the device token the NAS issued when `otp_code` was given.
+    /// The token of a trusted device, passed as `device_id`, stands in for the code on later logins.
+    pub async fn login(&self, username: &str, password: &str, otp_code: Option<&str>, device_id: Option<&str>) -> Result<Option<String>, SynologyError> {
+        let endpoint = self.discover_apis().await?.endpoint("SYNO.API.Auth")?;
+        let login_url = format!("{}/webapi/{}", self.base_url, endpoint.path);
+        let version = endpoint.version.to_string();
+
+        // A form body keeps the password out of URLs, and so out of proxy and server logs.
+        let mut form = vec![
+            ("api", "SYNO.API.Auth"),
+            ("version", version.as_str()),
+            ("method", "login"),
+            ("account", username),
+            ("passwd", password),
+            ("session", "FileStation"),
+            ("format", "sid"),
+        ];
+        // Device tokens came with version 6, older versions only take the code.
+        let device_tokens = endpoint.version >= 6;
+        if let Some(otp_code) = otp_code {
+            form.push(("otp_code", otp_code));
+            if device_tokens {
+                form.push(("enable_device_token", "yes"));
+                form.push(("device_name", DEVICE_NAME));
+            }
+        } else if let (Some(device_id), true) = (device_id, device_tokens) {
+            form.push(("device_id", device_id));
+            form.push(("device_name", DEVICE_NAME));
+        }
+        let result = self.client.post(login_url).form(&form).timeout(self.request_timeout).send().await;
 
         match result {
             Ok(res) => {
-                if res.status() == 200 {
-                    let login_result = res.json::<SynologyResult<LoginResult>>().await.unwrap();
-                    
-                    if login_result.success {
-                        self.sid = Some(login_result.data.sid);
-                        Ok(())
-                    }
-                    else {
-                        Err(-5)
+                self.check_certificate(&res)?;
+
+                if res.status() != 200 {
+                    error!("An error occurred when logging in: {}", res.status());
+
+                    return Err(SynologyError::Http(res.status().as_u16()));
+                }
+
+                let value = match res.json::<serde_json::Value>().await {
+                    Ok(value) => value,
+                    Err(error) => {
+                        error!("An error occurred when reading the login response: {}", error);
+
+                        return Err(SynologyError::InvalidResponse);
                     }
+                };
+
+                if value["success"].as_bool() != Some(true) {
+                    return Err(match value["error"]["code"].as_i64() {
+                        Some(code) => SynologyError::from_auth_code(code as i32),
+                        None => SynologyError::InvalidResponse
+                    });
                 }
-                else {
-                    error!("An error occurred when logging in: {}", res.status());
-                    Err(res.status().as_u16() as i32)
+
+                match serde_json::from_value::<SynologyResult<LoginResult>>(value) {
+                    Ok(login_result) => {
+                        let issued_device_id = login_result.data.did.filter(|did| !did.is_empty());
+
+                        *self.sid.lock().unwrap() = Some(login_result.data.sid);
+                        *self.credentials.lock().unwrap() = Some(Arc::new(Credentials {
+                            username: username.to_string(),
+                            password: password.to_string(),
+                            device_id: issued_device_id.clone().or(device_id.map(|device_id| device_id.to_string())),
+                        }));
+
+                        Ok(issued_device_id)
+                    },
+                    Err(error) => {
+                        error!("err: {} with login json.", error);
+
+                        Err(SynologyError::InvalidResponse)
+                    }
                 }
             },
-            Err(_error) => Err(-6)
+            Err(error) => {
+                error!("An error occurred when logging in: {}", error);
+
+                Err(Self::request_error(error))
+            }
         }
     }
 
-    pub async fn logout(&self) -> Result<(), i32> {
-        let mut additional = HashMap::new();
-        additional.insert("session", "FileStation");
+    pub async fn logout(&self) -> Result<(), SynologyError> {
+        let request = ApiRequest::new("SYNO.API.Auth", "logout").param("session", "FileStation");
+
+        let result = self.get(&request).await;
 
-        let result = self.get("SYN.API.Auth", 1, "logout", &additional, false).await;
-        // self.sid = Default::default();
+        // Do not log back in behind the user's back.
+        *self.credentials.lock().unwrap() = None;
+        *self.sid.lock().unwrap() = None;
 
-        return result;
+        result
     }
 
-    async fn get<T: DeserializeOwned>(&self, api: &str, version: u8, method: &str, additional: &HashMap<&str, &str>, allow_cache: bool) -> Result<T, i32> {
-        match &self.sid {
-            Some(sid) => {
-                let mut url = format!(
-                    "{}/webapi/entry.cgi?api={}&version={}&method={}&_sid={}",
-                    self.base_url,
-                    api,
-                    version,
-                    method,
-                    sid
-                );
+    async fn get<T: DeserializeOwned>(&self, request: &ApiRequest) -> Result<T, SynologyError> {
+        let endpoint = self.endpoint(request.api)?;
 
-                for (key, value) in &*additional {
-                    url += format!("&{}={}", key, value).as_ref();
-                }
+        let cache_key = request.cache_key();
+        let value = match self.cache.get(&cache_key) {
+            Some(value) => {
+                debug!("Using cache for {}.", cache_key);
 
-                debug!("url: {}", url);
+                value
+            },
+            None => {
+                // Anything that changes the NAS might have gone through before the connection failed.
+                let retry_policy = if IDEMPOTENT_REQUESTS.contains(&(request.api, request.method)) {
+                    self.retry_policy
+                } else {
+                    RetryPolicy::never()
+                };
 
-                let mut cache = self.cache.lock().unwrap();
-                if !allow_cache || !cache.contains_key(url.as_str()) || cache[url.as_str()].cache_time.elapsed().unwrap() > self.cache_lifetime {
-                    // Remove the key if it is in the cache.  It's expired.
-                    if cache.contains_key(url.as_str()) {
-                        cache.remove(url.as_str());
-                    }
+                let description = format!("{}.{}", request.api, request.method);
+                let value = retry_policy.run(&description, || self.get_once(request, &endpoint)).await?;
+                self.cache.insert(request, &value);
+
+                value
+            }
+        };
+
+        match SynologyResult::<T>::deserialize(&value) {
+            Ok(parsed) => Ok(parsed.data),
+            Err(error) => {
+                error!("err: {} with json '{}'.", error, value);
+
+                Err(SynologyError::InvalidResponse)
+            }
+        }
+    }
+
+    async fn get_once(&self, request: &ApiRequest, endpoint: &ApiEndpoint) -> Result<serde_json::Value, SynologyError> {
+        let sid = self.current_sid()?;
+
+        match self.get_with_sid(&sid, request, endpoint).await {
+            Err(error) if error.is_session_error() => {
+                let sid = self.renew_session(&sid).await?;
+
+                self.get_with_sid(&sid, request, endpoint).await
+            },
+            result => result
+        }
+    }
 
-                    let request_time = SystemTime::now();
-                    let result = reqwest::get(url.clone()).await;
-
-                    return match result {
-                        Ok(res) => {
-                            if res.status() == 200 {
-                                let text_result = res.text().await;
-    
-                                match text_result {
-                                    Ok(text) => {
-                                        let value_result = serde_json::from_str::<serde_json::Value>(text.as_str());
-    
-                                        match value_result {
-                                            Ok(value) => {
-                                                cache.insert(url, FileStationCacheItem { cache_time: request_time, data: value.clone() });
-
-                                                if !value["success"].as_bool().unwrap() {
-                                                    error!("success: false with json '{}'.", text);
-    
-                                                    Err(value["error"].as_object().unwrap()["code"].as_i64().unwrap() as i32)
-                                                } else {
-                                                    let parsed_result = serde_json::from_value::<SynologyResult<T>>(value);
-    
-                                                    match parsed_result {
-                                                        Ok(parsed) => Ok(parsed.data),
-                                                        Err(error) => {
-                                                            error!("err: {} with json '{}'.", error, text);
-    
-                                                            Err(-7)
-                                                        } 
-                                                    }
-                                                }
-                                            },
-                                            Err(error) => {
-                                                error!("err: {} with json '{}'.", error, text);
-    
-                                                Err(-8)
-                                            }
-                                        }
-                                    },
-                                    Err(error) => {
-                                        error!("{}", error);
-                                        Err(-9)
+    /// Calls the API, and returns the whole response if it succeeded.
+    async fn get_with_sid(&self, sid: &str, request: &ApiRequest, endpoint: &ApiEndpoint) -> Result<serde_json::Value, SynologyError> {
+        let url = request.url(&self.base_url, endpoint, Some(sid));
+
+        debug!("url: {}", url);
+
+        let result = self.client.get(url).timeout(self.request_timeout).send().await;
+
+        match result {
+            Ok(res) => {
+                self.check_certificate(&res)?;
+
+                if res.status() == 200 {
+                    let text_result = res.text().await;
+
+                    match text_result {
+                        Ok(text) => {
+                            let value_result = serde_json::from_str::<serde_json::Value>(text.as_str());
+
+                            match value_result {
+                                Ok(value) => {
+                                    if value["success"].as_bool() != Some(true) {
+                                        error!("success: false with json '{}'.", text);
+
+                                        Err(match value["error"]["code"].as_i64() {
+                                            Some(code) => SynologyError::from_code(code as i32),
+                                            None => SynologyError::InvalidResponse
+                                        })
+                                    } else {
+                                        Ok(value)
                                     }
+                                },
+                                Err(error) => {
+                                    error!("err: {} with json '{}'.", error, text);
+
+                                    Err(SynologyError::InvalidResponse)
                                 }
                             }
-                            else {
-                                Err(res.status().as_u16() as i32)
-                            }
                         },
-                        Err(error) => Err(error.status().unwrap().as_u16() as i32)
-                    }
-                } else {
-                    debug!("Using cache for url: {}.", url);
-                    let parsed_result = serde_json::from_value::<SynologyResult<T>>(cache[url.as_str()].data.clone());
-    
-                    match parsed_result {
-                        Ok(parsed) => Ok(parsed.data),
                         Err(error) => {
-                            error!("err: {} with cached json.", error);
-
-                            Err(-7)
-                        } 
+                            error!("{}", error);
+                            Err(Self::request_error(error))
+                        }
                     }
                 }
+                else {
+                    Err(SynologyError::Http(res.status().as_u16()))
+                }
             },
-            None => Err(403)
+            Err(error) => {
+                error!("An error occurred while calling {}: {}", request.api, error);
+
+                Err(Self::request_error(error))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::request::tests::{HOSTILE_NAMES, endpoint, hostile_paths, query_value};
+
+    fn sent_list(request: &ApiRequest, name: &str) -> Vec<String> {
+        let url = request.url("https://nas:5001", &endpoint(), Some("sid"));
+
+        serde_json::from_str(&query_value(&url, name)).unwrap()
+    }
+
+    #[test]
+    fn downloads_send_the_path_as_one_element() {
+        for path in hostile_paths() {
+            let request = FileStation::download_request(&path);
+
+            assert_eq!(sent_list(&request, "path"), vec![path.clone()]);
+            assert_eq!(request.get_paths(), &[path]);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn new_folders_send_the_folder_and_name_as_one_element_each() {
+        for (path, name) in hostile_paths().into_iter().zip(HOSTILE_NAMES) {
+            let request = FileStation::create_folder_request(&path, name);
+
+            assert_eq!(sent_list(&request, "folder_path"), vec![path.clone()]);
+            assert_eq!(sent_list(&request, "name"), vec![name.to_string()]);
+            assert_eq!(request.get_paths(), &[path]);
+        }
+    }
+
+    #[test]
+    fn renames_send_the_path_and_name_as_one_element_each() {
+        for (path, name) in hostile_paths().into_iter().zip(HOSTILE_NAMES) {
+            let request = FileStation::rename_request(&path, name);
+
+            assert_eq!(sent_list(&request, "path"), vec![path.clone()]);
+            assert_eq!(sent_list(&request, "name"), vec![name.to_string()]);
+            assert_eq!(request.get_paths(), &[path]);
+        }
+    }
+}
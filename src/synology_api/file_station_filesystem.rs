@@ -1,8 +1,12 @@
-use super::{FileStation, FileCache, epoch_from_seconds};
-use std::{time::{SystemTime, Duration}, collections::HashMap, sync::Mutex, io::{Error, Write}, fs::File};
+use crate::options::Options;
+
+use super::{FileStation, FileCache, SearchQuery, SynologyError, epoch_from_seconds, normalize_path, inode_table::InodeTable, device_tokens::DeviceTokens, request_batcher::RequestBatcher, worker_pool::WorkerPool};
+use super::responses::{Acl, FileAdditional, FileStationItem, ListSharesResult, Owner, ShareAdditional, VolumeStatus};
+use std::{time::{SystemTime, Duration}, collections::{HashMap, HashSet}, sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}}, io::{Error, Write}, fs::File};
 use tokio::runtime::{Runtime, Builder};
-use log::error;
+use log::{debug, error};
 
+#[derive(Clone)]
 pub struct FileSystemInfo {
     pub atime: SystemTime,
     pub mtime: SystemTime,
@@ -10,89 +14,409 @@ pub struct FileSystemInfo {
     pub crtime: SystemTime,
 
 	pub perm: u16,
+	pub acl: Acl,
+	pub is_acl_mode: bool,
 
 	pub name: String,
 	pub path: String,
     pub is_dir: bool,
     pub size: u64,
 	pub ino: u64,
+
+	/// Where a symlink on the NAS points to, as a path inside the mount.
+	pub link_target: Option<String>,
+	pub real_path: Option<String>,
+	pub owner: Option<Owner>,
+}
+
+/// DSM reports POSIX permissions as decimal digits that read like octal ones, so 755 means rwxr-xr-x.
+fn posix_mode(posix: u16) -> u16 {
+	match u16::from_str_radix(&posix.to_string(), 8) {
+		Ok(mode) => mode & 0o7777,
+		Err(_) => {
+			error!("The NAS reported the permissions {}, which are not octal digits.", posix);
+
+			0
+		}
+	}
 }
 
+impl FileSystemInfo {
+	fn from_share(share: &FileStationItem<ShareAdditional>, ino: u64) -> FileSystemInfo {
+		FileSystemInfo {
+			atime: epoch_from_seconds(share.additional.time.atime),
+			ctime: epoch_from_seconds(share.additional.time.ctime),
+			crtime: epoch_from_seconds(share.additional.time.crtime),
+			mtime: epoch_from_seconds(share.additional.time.mtime),
+			name: share.name.clone(),
+			path: share.path.clone(),
+			size: 0,
+			perm: posix_mode(share.additional.perm.posix),
+			acl: share.additional.perm.acl.clone(),
+			is_acl_mode: share.additional.perm.is_acl_mode,
+			is_dir: true,
+			ino,
+			link_target: None,
+			real_path: share.additional.real_path.clone(),
+			owner: share.additional.owner.clone()
+		}
+	}
+
+	fn from_file(file: &FileStationItem<FileAdditional>, ino: u64) -> FileSystemInfo {
+		let mut size: u64 = 0;
+		if !file.isdir {
+			size = file.additional.size;
+		}
+
+		FileSystemInfo {
+			atime: epoch_from_seconds(file.additional.time.atime),
+			ctime: epoch_from_seconds(file.additional.time.ctime),
+			crtime: epoch_from_seconds(file.additional.time.crtime),
+			mtime: epoch_from_seconds(file.additional.time.mtime),
+			name: file.name.clone(),
+			path: file.path.clone(),
+			size,
+			perm: posix_mode(file.additional.perm.posix),
+			acl: file.additional.perm.acl.clone(),
+			is_acl_mode: file.additional.perm.is_acl_mode,
+			is_dir: file.isdir,
+			ino,
+			link_target: None,
+			real_path: file.additional.real_path.clone(),
+			owner: file.additional.owner.clone()
+		}
+	}
+
+	/// Permission bits derived from the ACL flags the NAS reports for the logged-in user.
+	pub fn acl_perm(&self) -> u16 {
+		let mut bits: u16 = 0;
+		if self.acl.read {
+			bits |= 0o4;
+		}
+		if self.acl.write || self.acl.append {
+			bits |= 0o2;
+		}
+		if self.acl.exec {
+			bits |= 0o1;
+		}
+
+		(bits << 6) | (bits << 3) | bits
+	}
+}
+
+/// Upper bound on the number of threads serving requests at once.  Further requests wait for one to
+/// free up, rather than each starting a thread of its own when the NAS is slow.  These are not the
+/// runtime's blocking threads, which it needs for itself, e.g. to resolve host names.
+const REQUEST_THREAD_LIMIT: usize = 64;
+
+/// Upper bound on the number of missing paths remembered at once.
+const NEGATIVE_LOOKUP_LIMIT: usize = 16384;
+
+/// Upper bound on the number of paths looked up by a single getinfo call.
+const INFO_BATCH_LIMIT: usize = 100;
+
+/// Folder whose subfolders are named after searches, and hold links to whatever those searches find.
+const SEARCH_ROOT: &str = "/.search";
+
+/// How long the matches of a search are reused before the NAS is searched again.
+const SEARCH_RESULT_TTL: Duration = Duration::from_secs(60);
+
+/// Upper bound on the number of searches whose matches are kept at once.
+const SEARCH_RESULT_LIMIT: usize = 64;
+
+/// First inode number handed to the search folders and their entries.  These only live in memory,
+/// well clear of the numbers the inode table allocates.
+const SEARCH_INO_BASE: u64 = 1 << 62;
+
+/// Modification time and size of every entry of a folder, by name.
+type EntryStates = HashMap<String, (SystemTime, u64)>;
+
+/// What a watched folder looked like when it was last polled.
+struct DirectorySnapshot {
+	/// Changes whenever an entry is added, removed or renamed, so the entries only need listing again then.
+	mtime: SystemTime,
+	entries: EntryStates,
+}
+
+/// An entry that changed on the NAS, for the kernel to forget what it cached about it.
+pub struct RemoteChange {
+	pub path: String,
+	pub parent_ino: u64,
+	pub name: String,
+	/// Set when the kernel holds a reference to the entry.
+	pub ino: Option<u64>,
+}
+
+/// When the matches of a search expire, and the entries linking to them.
+type SearchResult = (SystemTime, Arc<Vec<FileSystemInfo>>);
+
 pub struct FileStationFileSystem {
     pub filestation: FileStation,
-	runtime: Runtime,
+	/// Only taken when dropped.
+	runtime: Option<Runtime>,
+	workers: WorkerPool,
 
 	path2ino: Mutex<HashMap<String, u64>>,
 	ino2path: Mutex<HashMap<u64, String>>,
+	lookup_counts: Mutex<HashMap<u64, u64>>,
+	watched_directories: Mutex<HashSet<u64>>,
+	directory_snapshots: Mutex<HashMap<u64, DirectorySnapshot>>,
+	negative_lookups: Mutex<HashMap<String, SystemTime>>,
+	negative_ttl: Duration,
+	share_real_paths: Mutex<HashMap<String, String>>,
+	/// The entries of each search folder, by the name of the search, until they expire.
+	search_results: Mutex<HashMap<String, SearchResult>>,
+	next_search_ino: AtomicU64,
+	info_batcher: RequestBatcher<FileStationItem<FileAdditional>>,
+	inode_table: InodeTable,
+	device_tokens: DeviceTokens,
 	file_cache: Mutex<FileCache>,
+	/// Held while a file is downloaded, so each file is only fetched once however many threads read it.
+	downloads: Mutex<HashMap<String, Arc<Mutex<()>>>>,
 }
 
 impl FileStationFileSystem {
-    pub fn new(hostname: &str, port: u16, secured: bool) -> Result<FileStationFileSystem, i32> {
+    pub fn new(hostname: &str, port: u16, secured: bool, options: &Options) -> Result<FileStationFileSystem, SynologyError> {
 		match FileCache::new(hostname) {
 			Ok(filecache) => {
 				let path2ino = HashMap::new();
 				let ino2path = HashMap::new();
 
-				let mut builder = Builder::new_current_thread();
+				let filestation = match FileStation::new(hostname, port, secured, options) {
+					Ok(filestation) => filestation,
+					Err(error) => return Err(error)
+				};
+				let inode_table = match filecache.get_inode_table() {
+					Ok(inode_table) => inode_table,
+					Err(error) => return Err(error)
+				};
+				let device_tokens = match filecache.get_device_tokens() {
+					Ok(device_tokens) => device_tokens,
+					Err(error) => return Err(error)
+				};
+
+				// Requests from several threads run their HTTP calls side by side.
+				let mut builder = Builder::new_multi_thread();
 
 				let filestation_filesystem = FileStationFileSystem {
-					filestation: FileStation::new(hostname, port, secured, Duration::from_secs(5)),
+					filestation,
 					path2ino: Mutex::new(path2ino),
 					ino2path: Mutex::new(ino2path),
+					lookup_counts: Mutex::new(HashMap::new()),
+					watched_directories: Mutex::new(HashSet::new()),
+					directory_snapshots: Mutex::new(HashMap::new()),
+					negative_lookups: Mutex::new(HashMap::new()),
+					negative_ttl: options.negative_ttl,
+					share_real_paths: Mutex::new(HashMap::new()),
+					search_results: Mutex::new(HashMap::new()),
+					next_search_ino: AtomicU64::new(SEARCH_INO_BASE),
+					info_batcher: RequestBatcher::new(options.batch_window, INFO_BATCH_LIMIT),
+					inode_table,
+					device_tokens,
 					file_cache: Mutex::new(filecache),
-					runtime: builder.enable_io().build().unwrap(),
+					downloads: Mutex::new(HashMap::new()),
+					runtime: Some(builder.enable_io().enable_time().build().unwrap()),
+					workers: WorkerPool::new("request", REQUEST_THREAD_LIMIT),
 				};
-				filestation_filesystem.insert_ino("/");
-				
-				Ok(filestation_filesystem)
+
+				match filestation_filesystem.insert_ino("/") {
+					Ok(ino) => {
+						// The kernel never forgets the root, so keep it mapped for the life of the mount.
+						filestation_filesystem.path2ino.lock().unwrap().insert("/".to_string(), ino);
+						filestation_filesystem.ino2path.lock().unwrap().insert(ino, "/".to_string());
+						filestation_filesystem.watched_directories.lock().unwrap().insert(ino);
+
+						Ok(filestation_filesystem)
+					},
+					Err(error) => Err(error)
+				}
 			},
 			Err(err) => Err(err)
 		}
     }
 
-	fn insert_ino(&self, path: &str) -> u64 {
-		let mut path2ino = self.path2ino.lock().unwrap();
-		let mut ino2path = self.ino2path.lock().unwrap();
+	fn insert_ino(&self, path: &str) -> Result<u64, SynologyError> {
+		let path_str = normalize_path(path);
+
+		if let Some(ino) = self.path2ino.lock().unwrap().get(&path_str) {
+			return Ok(*ino);
+		}
+
+		// Searches come and go, so they are never written to the persistent table.
+		if Self::is_search_path(&path_str) {
+			return Ok(self.search_ino(&path_str, &[]));
+		}
 
-		let mut path_str = path.to_string();
-		path_str = path_str.replace("//", "/");
-		if path_str.len() > 1 && path_str.ends_with("/") {
-			path_str = path_str.chars().take(path.len() - 1).skip(1).collect();
+		// Inode numbers come from the persistent table so they survive remounts.
+		// Only inodes the kernel holds a reference to are kept in memory.
+		self.inode_table.insert(&path_str)
+	}
+
+	/// Returns the inodes for `paths`, in the same order.  Those not held by the kernel are looked up or
+	/// allocated together.
+	fn insert_inos(&self, paths: &[String]) -> Result<Vec<u64>, SynologyError> {
+		let mut inos: Vec<Option<u64>> = Vec::with_capacity(paths.len());
+		let mut missing: Vec<String> = Vec::new();
+		{
+			let path2ino = self.path2ino.lock().unwrap();
+
+			for path in paths {
+				let path_str = normalize_path(path);
+				let ino = match path2ino.get(&path_str) {
+					Some(ino) => Some(*ino),
+					None if Self::is_search_path(&path_str) => None,
+					None => {
+						missing.push(path_str);
+						None
+					}
+				};
+				inos.push(ino);
+			}
 		}
 
-		let ino: u64;
-		if !path2ino.contains_key(&path_str) {
-			ino = (path2ino.len() + 1) as u64;
-			path2ino.insert(path_str.clone(), ino);
-			ino2path.insert(ino, path_str);
+		let allocated = if missing.is_empty() {
+			Vec::new()
 		} else {
-			ino = path2ino[&path_str];
+			match self.inode_table.insert_all(&missing) {
+				Ok(allocated) => allocated,
+				Err(error) => return Err(error)
+			}
+		};
+		let mut allocated = allocated.into_iter();
+
+		paths
+			.iter()
+			.zip(inos)
+			.map(|(path, ino)| match ino {
+				Some(ino) => Ok(ino),
+				None if Self::is_search_path(path) => self.insert_ino(path),
+				None => allocated.next().ok_or(SynologyError::Cache)
+			})
+			.collect()
+	}
+
+	#[cfg(target_family = "unix")]
+	pub fn get_path_for_ino(&self, ino: u64) -> Result<String, SynologyError> {
+		if let Some(path) = self.ino2path.lock().unwrap().get(&ino) {
+			return Ok(path.clone());
 		}
 
-		drop(path2ino);
-		drop(ino2path);
+		match self.inode_table.get_path(ino) {
+			Ok(Some(path)) => Ok(path),
+			Ok(None) => Err(SynologyError::NotFound),
+			Err(error) => Err(error)
+		}
+	}
+
+	/// Records that the kernel received a reference to `info` through a lookup reply.
+	#[cfg(target_family = "unix")]
+	pub fn increment_lookup_count(&self, info: &FileSystemInfo) {
+		let path_str = normalize_path(&info.path);
+
+		let mut path2ino = self.path2ino.lock().unwrap();
+		let mut ino2path = self.ino2path.lock().unwrap();
+		let mut lookup_counts = self.lookup_counts.lock().unwrap();
+
+		path2ino.insert(path_str.clone(), info.ino);
+		ino2path.insert(info.ino, path_str);
+		*lookup_counts.entry(info.ino).or_insert(0) += 1;
 
-		return ino;
+		// The kernel lists the target of a link, never the link itself.
+		if info.is_dir && info.link_target.is_none() {
+			self.watched_directories.lock().unwrap().insert(info.ino);
+		}
 	}
 
+	/// Drops `nlookup` kernel references to `ino`, evicting the in-memory mapping once none are left,
+	/// and returns whether that happened.  The persistent mapping is kept so the inode number stays the
+	/// same the next time it is looked up.
 	#[cfg(target_family = "unix")]
-	pub fn get_path_for_ino(&self, ino: u64) -> Result<String, i32> {
-		let ino2path = self.ino2path.lock();
-
-		match ino2path {
-			Ok(map) => {
-				let path = map[&ino].clone();
-				drop(map);
-				Ok(path)
+	pub fn forget(&self, ino: u64, nlookup: u64) -> bool {
+		let mut path2ino = self.path2ino.lock().unwrap();
+		let mut ino2path = self.ino2path.lock().unwrap();
+		let mut lookup_counts = self.lookup_counts.lock().unwrap();
+
+		let remaining = match lookup_counts.get_mut(&ino) {
+			Some(count) => {
+				*count = count.saturating_sub(nlookup);
+				*count
 			},
-			Err(_error) => Err(-1)
+			None => return false
+		};
+
+		if remaining == 0 {
+			lookup_counts.remove(&ino);
+
+			// The root is never looked up, so it is never forgotten either.
+			if ino != 1 {
+				if let Some(path) = ino2path.remove(&ino) {
+					path2ino.remove(&path);
+				}
+
+				self.watched_directories.lock().unwrap().remove(&ino);
+				self.directory_snapshots.lock().unwrap().remove(&ino);
+			}
+		}
+
+		remaining == 0
+	}
+
+	#[cfg(target_family = "unix")]
+	pub fn rename(&self, path: &str, new_name: &str) -> Result<(), SynologyError> {
+		let old_path = normalize_path(path);
+		let parent_path = match old_path.rfind('/') {
+			Some(index) => old_path[..index].to_string(),
+			None => "".to_string()
+		};
+		let new_path = normalize_path(format!("{}/{}", parent_path, new_name).as_str());
+
+		if Self::is_search_path(&old_path) {
+			return Err(SynologyError::ReadOnlyFileSystem);
+		}
+
+		match self.runtime().block_on(self.filestation.rename(&old_path, new_name)) {
+			Ok(_) => {
+				self.clear_negative_lookup(&new_path);
+
+				// Keep the existing inode numbers, so the kernel sees the same files under their new name.
+				let result = self.inode_table.rename(&old_path, &new_path);
+
+				let mut path2ino = self.path2ino.lock().unwrap();
+				let mut ino2path = self.ino2path.lock().unwrap();
+				let old_prefix = format!("{}/", old_path);
+				let new_prefix = format!("{}/", new_path);
+
+				path2ino.retain(|path, ino| {
+					let replaced = *path == new_path || path.starts_with(&new_prefix);
+					if replaced {
+						ino2path.remove(ino);
+					}
+
+					!replaced
+				});
+				let moved: Vec<(String, u64)> = path2ino
+					.iter()
+					.filter(|(path, _)| **path == old_path || path.starts_with(&old_prefix))
+					.map(|(path, ino)| (path.clone(), *ino))
+					.collect();
+
+				for (path, ino) in moved {
+					let renamed_path = format!("{}{}", new_path, &path[old_path.len()..]);
+
+					path2ino.remove(&path);
+					path2ino.insert(renamed_path.clone(), ino);
+					ino2path.insert(ino, renamed_path);
+				}
+
+				result
+			},
+			Err(error) => Err(error)
 		}
 	}
 
 	#[cfg(target_family = "windows")]
-	pub fn get_free_space(&self) -> Result<(u64, u64), i32> {
-		let shares = self.runtime.block_on(self.filestation.list_shares());
+	pub fn get_free_space(&self) -> Result<(u64, u64), SynologyError> {
+		let shares = self.list_shares();
 
 		match shares {
 			Ok(res) => {
@@ -114,13 +438,98 @@ impl FileStationFileSystem {
 		}
 	}
     
-    pub fn get_info(&self, file_name: &str) -> Result<FileSystemInfo, i32> {
+	/// Forgets that `path` was missing, now that this mount created it.  The failed getinfo
+	/// may still sit in the response cache, so it goes as well.
+	fn clear_negative_lookup(&self, path: &str) {
+		let path_str = normalize_path(path);
+
+		self.negative_lookups.lock().unwrap().remove(&path_str);
+		self.filestation.invalidate_path(&path_str);
+	}
+
+	fn remember_negative_lookup(&self, path: &str) {
+		if self.negative_ttl.is_zero() {
+			return;
+		}
+
+		let mut negative_lookups = self.negative_lookups.lock().unwrap();
+		let now = SystemTime::now();
+
+		// Probing tools can ask for a lot of missing names, so drop the expired ones now and then.
+		if negative_lookups.len() >= NEGATIVE_LOOKUP_LIMIT {
+			negative_lookups.retain(|_, expires| *expires > now);
+		}
+
+		if negative_lookups.len() < NEGATIVE_LOOKUP_LIMIT {
+			negative_lookups.insert(normalize_path(path), now + self.negative_ttl);
+		}
+	}
+
+	fn is_negative_lookup(&self, path: &str) -> bool {
+		let mut negative_lookups = self.negative_lookups.lock().unwrap();
+		let path_str = normalize_path(path);
+
+		match negative_lookups.get(&path_str) {
+			Some(expires) if *expires > SystemTime::now() => true,
+			Some(_) => {
+				negative_lookups.remove(&path_str);
+				false
+			},
+			None => false
+		}
+	}
+
+    pub fn get_info(&self, file_name: &str) -> Result<FileSystemInfo, SynologyError> {
+		if self.is_negative_lookup(file_name) {
+			return Err(SynologyError::NotFound);
+		}
+
+		let result = self.fetch_info(file_name);
+		if let Err(error) = result {
+			if error.is_not_found() {
+				self.remember_negative_lookup(file_name);
+			}
+		}
+
+		result
+	}
+
+    fn fetch_info(&self, file_name: &str) -> Result<FileSystemInfo, SynologyError> {
 		let file_name_str = file_name.to_string();
 
+		if file_name_str == SEARCH_ROOT {
+			return self.search_folder_info(&file_name_str);
+		}
+
+		if let Some((search_name, entry_name)) = Self::split_search_path(&file_name_str) {
+			// Only listing a search runs it, so probing for names in the search folder costs nothing.
+			return match entry_name {
+				Some(entry_name) => {
+					let search_results = self.search_results.lock().unwrap();
+					let entry = search_results
+						.get(search_name)
+						.and_then(|(_, entries)| entries.iter().find(|entry| entry.name == entry_name));
+
+					match entry {
+						Some(entry) => Ok(entry.clone()),
+						None => Err(SynologyError::NotFound)
+					}
+				},
+				None => match SearchQuery::from_name(search_name) {
+					Ok(_) => self.search_folder_info(&file_name_str),
+					Err(message) => {
+						debug!("{} is not a search: {}", search_name, message);
+
+						Err(SynologyError::NotFound)
+					}
+				}
+			};
+		}
+
 		if file_name_str == "/" {
-			let shares = self.runtime.block_on(self.filestation.list_shares());
+			let shares = self.list_shares();
 
-			return match shares {
+			match shares {
 				Ok(res) => {
 					let mut totalspace: u64 = 0;
 					let mut atime: u64 = 0;
@@ -139,8 +548,10 @@ impl FileStationFileSystem {
 						}
 					}
 
-					let path2ino = self.path2ino.lock().unwrap();
-					let ino = path2ino[&file_name_str];
+					let ino = match self.insert_ino(&file_name_str) {
+						Ok(ino) => ino,
+						Err(error) => return Err(error)
+					};
 
 					Ok(FileSystemInfo {
                         atime: epoch_from_seconds(atime),
@@ -149,168 +560,610 @@ impl FileStationFileSystem {
                         mtime: epoch_from_seconds(mtime),
 						ino,
 						perm: 0o755,
+						acl: Acl { read: true, exec: true, ..Default::default() },
+						is_acl_mode: false,
 						name: file_name_str.clone(),
 						path: file_name_str,
 						size: 0,
 						is_dir: true,
+						link_target: None,
+						real_path: None,
+						owner: None,
 					})
 				},
-				Err(error) => {
-					return Err(error);
-				}
+				Err(error) => Err(error)
 			}
-		} else if file_name_str.matches("/").count() == 1 {
-			let shares = self.runtime.block_on(self.filestation.list_shares());
+		} else if file_name_str.matches('/').count() == 1 {
+			let shares = self.list_shares();
 
-			return match shares {
+			match shares {
 				Ok(res) => {
 					for share in res.shares.iter() {
 						if share.path == file_name_str {
-							let ino = self.insert_ino(&file_name_str);
-
-							return Ok(FileSystemInfo {
-                                atime: epoch_from_seconds(share.additional.time.atime),
-                                ctime: epoch_from_seconds(share.additional.time.ctime),
-                                crtime: epoch_from_seconds(share.additional.time.crtime),
-                                mtime: epoch_from_seconds(share.additional.time.mtime),
-								name: share.name.clone(),
-								path: share.path.clone(),
-								size: 0,
-								perm: share.additional.perm.posix,
-								is_dir: true,
-								ino
-							});
+							return match self.insert_ino(&file_name_str) {
+								Ok(ino) => Ok(FileSystemInfo::from_share(share, ino)),
+								Err(error) => Err(error)
+							};
 						}
 					}
 
 					error!("No matching share was found: {}", file_name_str);
-					return Err(2);
+					Err(SynologyError::NotFound)
 				},
 				Err(error) => Err(error)
 			}
 		} else {
-			let files_result = self.runtime.block_on(self.filestation.get_info_for_path(&file_name_str));
+			// Lookups of siblings often arrive together, so they share a single getinfo call.
+			let files_result = self.info_batcher.request(&file_name_str, |paths| self.fetch_file_infos(paths));
 
-			return match files_result {
+			match files_result {
 				Ok(file) => {
-					let ino = self.insert_ino(&file_name_str);
-
-					let mut size: u64 = 0;
-					if !file.isdir {
-						size = file.additional.size;
+					match self.insert_ino(&file_name_str) {
+						Ok(ino) => Ok(self.file_info(&file, ino)),
+						Err(error) => Err(error)
 					}
-
-					return Ok(FileSystemInfo {
-                        atime: epoch_from_seconds(file.additional.time.atime),
-                        ctime: epoch_from_seconds(file.additional.time.ctime),
-                        crtime: epoch_from_seconds(file.additional.time.crtime),
-                        mtime: epoch_from_seconds(file.additional.time.mtime),
-						name: file.name,
-						path: file.path,
-                        size,
-						perm: file.additional.perm.posix,
-                        is_dir: file.isdir,
-						ino
-					});
 				},
 				Err(error) => Err(error)
 			}
 		}
 	}
 
-	pub fn list_files(&self, path: &str) -> Result<Vec<FileSystemInfo>, i32> {
+	/// Lists the shares, remembering where each of them lives on its volume.
+	fn list_shares(&self) -> Result<ListSharesResult, SynologyError> {
+		let result = self.runtime().block_on(self.filestation.list_shares());
+
+		if let Ok(res) = &result {
+			let mut share_real_paths = self.share_real_paths.lock().unwrap();
+			for share in res.shares.iter() {
+				if let Some(real_path) = &share.additional.real_path {
+					share_real_paths.insert(real_path.clone(), share.path.clone());
+				}
+			}
+		}
+
+		result
+	}
+
+	fn file_info(&self, file: &FileStationItem<FileAdditional>, ino: u64) -> FileSystemInfo {
+		let mut info = FileSystemInfo::from_file(file, ino);
+		info.link_target = self.resolve_link(file);
+
+		info
+	}
+
+	/// FileStation resolves links in `real_path`, so an entry whose real path leads elsewhere in the
+	/// shares is a symlink.  Links leading outside the shares are reported as their target.
+	fn resolve_link(&self, file: &FileStationItem<FileAdditional>) -> Option<String> {
+		let real_path = file.additional.real_path.as_ref()?;
+
+		if self.share_real_paths.lock().unwrap().is_empty() {
+			if let Err(error) = self.list_shares() {
+				error!("An error occurred while listing the shares to resolve {}: {}", real_path, error);
+			}
+		}
+
+		link_target(&self.share_real_paths.lock().unwrap(), &file.path, real_path)
+	}
+
+	fn fetch_file_infos(&self, paths: &[String]) -> Vec<Result<FileStationItem<FileAdditional>, SynologyError>> {
+		let path_refs: Vec<&str> = paths.iter().map(|path| path.as_str()).collect();
+
+		match self.runtime().block_on(self.filestation.get_info_for_paths(path_refs)) {
+			Ok(files) => files,
+			Err(error) => paths.iter().map(|_| Err(error)).collect()
+		}
+	}
+
+	#[cfg(target_family = "windows")]
+	pub fn list_files(&self, path: &str) -> Result<Vec<FileSystemInfo>, SynologyError> {
+		match self.list_files_page(path, 0, 0) {
+			Ok((files, _total)) => Ok(files),
+			Err(error) => Err(error)
+		}
+	}
+
+	/// Lists `limit` entries of the folder starting at `offset`, along with the total number of entries.
+	/// A limit of 0 lists everything.
+	pub fn list_files_page(&self, path: &str, offset: u64, limit: u64) -> Result<(Vec<FileSystemInfo>, u64), SynologyError> {
+		if path == SEARCH_ROOT {
+			// Searches come into being when they are looked up, so there is nothing to list.
+			return Ok((Vec::new(), 0));
+		}
+
+		if let Some((search_name, None)) = Self::split_search_path(path) {
+			return match self.search_entries(search_name) {
+				Ok(entries) => {
+					let take = if limit == 0 { entries.len() } else { limit as usize };
+					let found_files = entries.iter().skip(offset as usize).take(take).cloned().collect();
+
+					Ok((found_files, entries.len() as u64))
+				},
+				Err(error) => Err(error)
+			};
+		}
+
 		if path == "/" {
-			let shares = self.runtime.block_on(self.filestation.list_shares());
+			let shares = self.list_shares();
 
 			return match shares {
 				Ok(res) => {
 					let mut found_files: Vec<FileSystemInfo> = Vec::new();
 
-					for share in res.shares.iter() {
-						let ino = self.insert_ino(&share.path);
-
-						found_files.push(FileSystemInfo {
-							atime: epoch_from_seconds(share.additional.time.atime),
-							crtime: epoch_from_seconds(share.additional.time.crtime),
-							ctime: epoch_from_seconds(share.additional.time.ctime),
-							mtime: epoch_from_seconds(share.additional.time.mtime),
-							name: share.name.clone(),
-							path: share.path.clone(),
-							size: 0,
-							ino,
-							perm: share.additional.perm.posix,
-							is_dir: true
-						});
+					// There are only ever a handful of shares, so they come back in one go.
+					let take = if limit == 0 { res.shares.len() } else { limit as usize };
+					let shares: Vec<&FileStationItem<ShareAdditional>> = res.shares.iter().skip(offset as usize).take(take).collect();
+					let paths: Vec<String> = shares.iter().map(|share| share.path.clone()).collect();
+					let inos = match self.insert_inos(&paths) {
+						Ok(inos) => inos,
+						Err(error) => return Err(error)
+					};
+
+					for (share, ino) in shares.into_iter().zip(inos) {
+						found_files.push(FileSystemInfo::from_share(share, ino));
 					}
 
-					return Ok(found_files);
+					Ok((found_files, res.shares.len() as u64))
 				},
 				Err(error) => Err(error)
 			}
 		}
 		
-		let files = self.runtime.block_on(self.filestation.list_files(path));
+		let files = self.runtime().block_on(self.filestation.list_files(path, offset, limit));
 		match files {
 			Ok(res) => {
 				let mut found_files: Vec<FileSystemInfo> = Vec::new();
 
-				for file in res.files.iter() {
-					let ino = self.insert_ino(&file.path);
+				// A page can hold thousands of entries, so their inodes are allocated in one go.
+				let paths: Vec<String> = res.files.iter().map(|file| file.path.clone()).collect();
+				let inos = match self.insert_inos(&paths) {
+					Ok(inos) => inos,
+					Err(error) => return Err(error)
+				};
+
+				for (file, ino) in res.files.iter().zip(inos) {
+					found_files.push(self.file_info(file, ino));
+				}
 
-					let mut file_size: u64 = 0;
-					if !file.isdir {
-						file_size = file.additional.size;
+				let total = match res.total {
+					Some(total) => total as u64,
+					None => offset + found_files.len() as u64
+				};
+
+				Ok((found_files, total))
+			},
+			Err(error) => Err(error)
+		}
+	}
+
+	#[cfg(target_family = "unix")]
+	pub fn get_ino_for_path(&self, path: &str) -> Result<u64, SynologyError> {
+		self.insert_ino(path)
+	}
+
+	/// Checks every folder the kernel holds a reference to for entries that were added, removed or
+	/// renamed on the NAS.  Only folders whose modification time moved are listed again, so files that
+	/// merely changed in place show up once the kernel's TTLs expire.  Cached copies of changed files
+	/// are dropped.
+	#[cfg(target_family = "unix")]
+	pub fn poll_remote_changes(&self) -> Vec<RemoteChange> {
+		let directories: Vec<(u64, String)> = {
+			let ino2path = self.ino2path.lock().unwrap();
+			let watched_directories = self.watched_directories.lock().unwrap();
+
+			watched_directories
+				.iter()
+				.filter_map(|ino| ino2path.get(ino).map(|path| (*ino, path.clone())))
+				.filter(|(_, path)| !Self::is_search_path(path))
+				.collect()
+		};
+
+		let mtimes = self.fetch_directory_mtimes(&directories);
+
+		let mut changes: Vec<RemoteChange> = Vec::new();
+		for (ino, path) in directories {
+			// The list of shares has no modification time of its own, but it is short and cached anyway.
+			let mtime = match mtimes.get(&path) {
+				Some(mtime) => *mtime,
+				None if path == "/" => SystemTime::UNIX_EPOCH,
+				None => continue
+			};
+
+			let unchanged = path != "/" && matches!(
+				self.directory_snapshots.lock().unwrap().get(&ino),
+				Some(snapshot) if snapshot.mtime == mtime);
+			if unchanged {
+				continue;
+			}
+
+			let entries = match self.list_entry_states(&path) {
+				Ok(entries) => entries,
+				Err(error) => {
+					error!("An error occurred while polling {} for changes: {}", path, error);
+					continue;
+				}
+			};
+
+			// The first listing of a folder only serves as the baseline.
+			let changed_names: Vec<String> = {
+				let mut directory_snapshots = self.directory_snapshots.lock().unwrap();
+				let changed_names = match directory_snapshots.get(&ino) {
+					Some(previous) => previous.entries
+						.iter()
+						.filter(|(name, state)| entries.get(*name) != Some(*state))
+						.map(|(name, _)| name.clone())
+						.chain(entries.keys().filter(|name| !previous.entries.contains_key(*name)).cloned())
+						.collect(),
+					None => Vec::new()
+				};
+				directory_snapshots.insert(ino, DirectorySnapshot { mtime, entries });
+
+				changed_names
+			};
+
+			for name in changed_names {
+				let changed_path = normalize_path(format!("{}/{}", path, name).as_str());
+				let changed_ino = self.path2ino.lock().unwrap().get(&changed_path).copied();
+
+				changes.push(RemoteChange {
+					path: changed_path,
+					parent_ino: ino,
+					name,
+					ino: changed_ino,
+				});
+			}
+		}
+
+		for change in changes.iter() {
+			self.clear_negative_lookup(&change.path);
+			if let Err(error) = self.file_cache.lock().unwrap().remove_file_cache(&change.path) {
+				error!("An error occurred while dropping the cached copy of {}: {}", change.path, error);
+			}
+		}
+
+		changes
+	}
+
+	/// Modification times of the given folders.  Shares come from the list of shares, everything
+	/// else from batched getinfo calls.  Folders that could not be looked up are left out.
+	#[cfg(target_family = "unix")]
+	fn fetch_directory_mtimes(&self, directories: &[(u64, String)]) -> HashMap<String, SystemTime> {
+		let mut mtimes: HashMap<String, SystemTime> = HashMap::new();
+
+		if directories.iter().any(|(_, path)| path != "/" && path.matches('/').count() == 1) {
+			match self.list_shares() {
+				Ok(res) => {
+					for share in res.shares.iter() {
+						mtimes.insert(share.path.clone(), epoch_from_seconds(share.additional.time.mtime));
 					}
+				},
+				Err(error) => error!("An error occurred while listing the shares for changes: {}", error)
+			}
+		}
 
-					found_files.push(FileSystemInfo {
-						atime: epoch_from_seconds(file.additional.time.atime),
-						crtime: epoch_from_seconds(file.additional.time.crtime),
-						ctime: epoch_from_seconds(file.additional.time.ctime),
-						mtime: epoch_from_seconds(file.additional.time.mtime),
-						name: file.name.clone(),
-						path: file.path.clone(),
-						size: file_size,
-						perm: file.additional.perm.posix,
-						ino,
-						is_dir: file.isdir
-					});
+		let folders: Vec<String> = directories
+			.iter()
+			.filter(|(_, path)| path.matches('/').count() > 1)
+			.map(|(_, path)| path.clone())
+			.collect();
+
+		for chunk in folders.chunks(INFO_BATCH_LIMIT) {
+			for (path, result) in chunk.iter().zip(self.fetch_file_infos(chunk)) {
+				if let Ok(folder) = result {
+					mtimes.insert(path.clone(), epoch_from_seconds(folder.additional.time.mtime));
 				}
+			}
+		}
+
+		mtimes
+	}
+
+	/// The modification time and size of every entry of a folder, without allocating inode numbers.
+	#[cfg(target_family = "unix")]
+	fn list_entry_states(&self, path: &str) -> Result<EntryStates, SynologyError> {
+		if path == "/" {
+			return match self.list_shares() {
+				Ok(res) => Ok(res.shares
+					.iter()
+					.map(|share| (share.name.clone(), (epoch_from_seconds(share.additional.time.mtime), 0)))
+					.collect()),
+				Err(error) => Err(error)
+			};
+		}
+
+		match self.runtime().block_on(self.filestation.list_files(path, 0, 0)) {
+			Ok(res) => Ok(res.files
+				.iter()
+				.map(|file| {
+					let size = if file.isdir { 0 } else { file.additional.size };
+					(file.name.clone(), (epoch_from_seconds(file.additional.time.mtime), size))
+				})
+				.collect()),
+			Err(error) => Err(error)
+		}
+	}
+
+	/// The status of the volume holding the share `path` lives in.
+	#[cfg(target_family = "unix")]
+	pub fn get_volume_status(&self, path: &str) -> Result<Option<VolumeStatus>, SynologyError> {
+		let share_path = match normalize_path(path).split('/').nth(1) {
+			Some(share_name) if !share_name.is_empty() => format!("/{}", share_name),
+			_ => return Ok(None)
+		};
+
+		match self.list_shares() {
+			Ok(res) => Ok(res.shares
+				.into_iter()
+				.find(|share| share.path == share_path)
+				.map(|share| share.additional.volume_status)),
+			Err(error) => Err(error)
+		}
+	}
+
+	#[cfg(target_family = "unix")]
+	pub fn get_md5(&self, path: &str) -> Result<String, SynologyError> {
+		self.runtime().block_on(self.filestation.md5(path))
+	}
+
+	/// Asks the NAS whether the logged-in user may write `name` inside `folder_path`.
+	#[cfg(target_family = "unix")]
+	pub fn check_write_permission(&self, folder_path: &str, name: &str, overwrite: bool) -> Result<(), SynologyError> {
+		// Neither the root nor the list of shares can be written through FileStation.
+		if normalize_path(folder_path) == "/" {
+			return Err(SynologyError::PermissionDenied);
+		}
 
-				return Ok(found_files);
+		// Searches only hold links to what they found.
+		if Self::is_search_path(folder_path) {
+			return Err(SynologyError::ReadOnlyFileSystem);
+		}
+
+		self.runtime().block_on(self.filestation.check_permission(&normalize_path(folder_path), name, overwrite))
+	}
+
+	#[cfg(target_family = "unix")]
+	pub fn create_folder(&self, folder_path: &str, name: &str) -> Result<FileSystemInfo, SynologyError> {
+		match self.runtime().block_on(self.filestation.create_folder(&normalize_path(folder_path), name)) {
+			Ok(res) => {
+				match res.folders.first() {
+					Some(folder) => {
+						self.clear_negative_lookup(&folder.path);
+
+						match self.insert_ino(&folder.path) {
+							Ok(ino) => Ok(self.file_info(folder, ino)),
+							Err(error) => Err(error)
+						}
+					},
+					None => {
+						error!("The NAS did not return the folder it created: {}/{}", folder_path, name);
+
+						Err(SynologyError::InvalidResponse)
+					}
+				}
 			},
 			Err(error) => Err(error)
 		}
 	}
 
-    pub fn login(&mut self, username: &str, password: &str) -> Result<(), i32> {
-        self.runtime.block_on(self.filestation.login(username, password))
-    }
+	/// Searches the NAS, every share unless `query` names folders, and returns the paths of the matches.
+	pub fn search(&self, query: &SearchQuery) -> Result<Vec<String>, SynologyError> {
+		match self.search_files(query) {
+			Ok(files) => Ok(files.into_iter().map(|file| file.path).collect()),
+			Err(error) => Err(error)
+		}
+	}
+
+	fn search_files(&self, query: &SearchQuery) -> Result<Vec<FileStationItem<FileAdditional>>, SynologyError> {
+		let mut query = query.clone();
+		if query.folders.is_empty() {
+			match self.list_shares() {
+				Ok(res) => query.folders = res.shares.into_iter().map(|share| share.path).collect(),
+				Err(error) => return Err(error)
+			}
+		}
+
+		self.runtime().block_on(self.filestation.search(&query))
+	}
 
-	pub fn logout(&self) -> Result<(), i32> {
-		self.runtime.block_on(self.filestation.logout())
+	/// Splits a path below the search folder into the name of the search and, for the entries of a
+	/// search, the name of the entry.
+	fn split_search_path(path: &str) -> Option<(&str, Option<&str>)> {
+		let rest = path.strip_prefix(SEARCH_ROOT)?.strip_prefix('/')?;
+
+		match rest.split_once('/') {
+			Some((search_name, entry_name)) => Some((search_name, Some(entry_name))),
+			None => Some((rest, None))
+		}
+	}
+
+	/// Whether `path` is the search folder or lies within it, rather than in one of the shares.
+	pub fn is_search_path(path: &str) -> bool {
+		let path_str = normalize_path(path);
+
+		path_str == SEARCH_ROOT || Self::split_search_path(&path_str).is_some()
+	}
+
+	/// The inode number of a search folder or entry: the one the kernel holds, or else the one it had in
+	/// the previous matches of its search, or else a new one.
+	fn search_ino(&self, path: &str, previous_entries: &[FileSystemInfo]) -> u64 {
+		if let Some(ino) = self.path2ino.lock().unwrap().get(path) {
+			return *ino;
+		}
+
+		match previous_entries.iter().find(|entry| entry.path == path) {
+			Some(entry) => entry.ino,
+			None => self.next_search_ino.fetch_add(1, Ordering::Relaxed)
+		}
+	}
+
+	/// The search folder and the folders of each search exist nowhere on the NAS, and cannot be written.
+	fn search_folder_info(&self, path: &str) -> Result<FileSystemInfo, SynologyError> {
+		let ino = self.search_ino(path, &[]);
+		let now = SystemTime::now();
+
+		Ok(FileSystemInfo {
+			atime: now,
+			ctime: now,
+			crtime: now,
+			mtime: now,
+			ino,
+			perm: 0o555,
+			acl: Acl { read: true, exec: true, ..Default::default() },
+			is_acl_mode: false,
+			name: path.rsplit('/').next().unwrap_or_default().to_string(),
+			path: path.to_string(),
+			size: 0,
+			is_dir: true,
+			link_target: None,
+			real_path: None,
+			owner: None,
+		})
+	}
+
+	/// Runs the search a search folder is named after, unless it ran recently, and returns its entries:
+	/// one link per match, leading to the matching file or folder.  Only listing the folder gets here.
+	fn search_entries(&self, search_name: &str) -> Result<Arc<Vec<FileSystemInfo>>, SynologyError> {
+		let previous_entries = match self.search_results.lock().unwrap().get(search_name) {
+			Some((expires, entries)) if *expires > SystemTime::now() => return Ok(entries.clone()),
+			Some((_, entries)) => entries.clone(),
+			None => Arc::new(Vec::new())
+		};
+
+		let query = match SearchQuery::from_name(search_name) {
+			Ok(query) => query,
+			Err(message) => {
+				error!("{} is not a search: {}", search_name, message);
+
+				return Err(SynologyError::InvalidParameter);
+			}
+		};
+
+		let files = match self.search_files(&query) {
+			Ok(files) => files,
+			Err(error) => return Err(error)
+		};
+
+		let mut names: HashSet<String> = HashSet::new();
+		let mut entries: Vec<FileSystemInfo> = Vec::new();
+		for file in files.iter() {
+			// Matches in different folders can share a name, so the later ones are numbered.
+			let mut name = file.name.clone();
+			let mut copy = 1;
+			while names.contains(&name) {
+				copy += 1;
+				name = format!("{} ({})", file.name, copy);
+			}
+			names.insert(name.clone());
+
+			let path = format!("{}/{}/{}", SEARCH_ROOT, search_name, name);
+			let ino = self.search_ino(&path, &previous_entries);
+
+			let mut info = FileSystemInfo::from_file(file, ino);
+			info.link_target = Some(self.resolve_link(file).unwrap_or_else(|| normalize_path(&file.path)));
+			info.name = name;
+			info.path = path;
+			entries.push(info);
+		}
+
+		// Names looked up before the search ran may exist now.
+		let prefix = format!("{}/{}/", SEARCH_ROOT, search_name);
+		self.negative_lookups.lock().unwrap().retain(|path, _| !path.starts_with(&prefix));
+
+		let entries = Arc::new(entries);
+		let mut search_results = self.search_results.lock().unwrap();
+		let now = SystemTime::now();
+
+		if search_results.len() >= SEARCH_RESULT_LIMIT {
+			search_results.retain(|_, (expires, _)| *expires > now);
+		}
+
+		if search_results.len() < SEARCH_RESULT_LIMIT {
+			search_results.insert(search_name.to_string(), (now + SEARCH_RESULT_TTL, entries.clone()));
+		}
+
+		Ok(entries)
+	}
+
+	/// Logs in as a trusted device if this account verified itself before.  Should the NAS still ask for
+	/// a 2-step verification code, `otp_code` is called for one, and the device token issued in return is
+	/// remembered for the next login.
+	pub fn login(&self, username: &str, password: &str, otp_code: impl FnOnce() -> Option<String>) -> Result<(), SynologyError> {
+		let device_id = self.device_tokens.get(username);
+		match self.runtime().block_on(self.filestation.login(username, password, None, device_id.as_deref())) {
+			Ok(_) => Ok(()),
+			Err(SynologyError::OtpRequired) => {
+				let otp_code = match otp_code() {
+					Some(otp_code) => otp_code,
+					None => return Err(SynologyError::OtpRequired)
+				};
+
+				match self.runtime().block_on(self.filestation.login(username, password, Some(otp_code.trim()), None)) {
+					Ok(Some(device_id)) => {
+						// Failing to remember the device only means being asked again next time.
+						if let Err(error) = self.device_tokens.set(username, &device_id) {
+							error!("Could not remember this device for {}: {}", username, error);
+						}
+
+						Ok(())
+					},
+					Ok(None) => Ok(()),
+					Err(error) => Err(error)
+				}
+			},
+			Err(error) => Err(error)
+		}
+	}
+
+	pub fn logout(&self) -> Result<(), SynologyError> {
+		self.runtime().block_on(self.filestation.logout())
 	}
 
-	pub fn read_bytes(&self, path: &str, offset: i64, buffer: &mut [u8]) -> Result<u64, i32> {
-		let cache = self.file_cache.lock().unwrap();
+	fn runtime(&self) -> &Runtime {
+		self.runtime.as_ref().unwrap()
+	}
+
+	/// Runs `work` on a thread of its own, for requests that should not wait for one another.
+	pub fn spawn(&self, work: impl FnOnce() + Send + 'static) {
+		self.workers.execute(work);
+	}
+
+	pub fn read_bytes(&self, path: &str, offset: i64, buffer: &mut [u8]) -> Result<u64, SynologyError> {
+		let download = self.downloads.lock().unwrap().entry(path.to_string()).or_default().clone();
+
+		let result = {
+			let _downloading = download.lock().unwrap();
+			self.read_bytes_downloaded(path, offset, buffer)
+		};
+
+		// Forget the lock once nobody else is waiting for it.
+		let mut downloads = self.downloads.lock().unwrap();
+		if Arc::strong_count(&download) == 2 {
+			downloads.remove(path);
+		}
+
+		result
+	}
+
+	fn read_bytes_downloaded(&self, path: &str, offset: i64, buffer: &mut [u8]) -> Result<u64, SynologyError> {
 		match self.get_info(path) {
 			Ok(info) => {
-				if !cache.is_file_cached(&info) {
-					let file_result = cache.create_file_cache(&info);
+				let is_cached = self.file_cache.lock().unwrap().is_file_cached(&info);
+				if !is_cached {
+					let file_result = self.file_cache.lock().unwrap().create_file_cache(&info);
 
 					if file_result.is_err() {
 						return Err(file_result.err().unwrap());
 					}
 
 					let file_mutex = Mutex::new(file_result.unwrap());
-					let result = self.runtime.block_on(self.filestation.download(path, |bytes| {
+					let result = self.runtime().block_on(self.filestation.download(path, |bytes| {
 						let mut file = file_mutex.lock().unwrap();
-						let write_result = file.write_all(&bytes.to_vec());
+						let write_result = file.write_all(&bytes);
 						
 						if write_result.is_err() {
-							return Err(-1);
+							return Err(SynologyError::Cache);
 						}
 
-						return Ok(());
+						Ok(())
 					}));
 					drop(file_mutex);
 
@@ -319,21 +1172,22 @@ impl FileStationFileSystem {
 					}
 				}
 
-				match cache.get_file_cache(&info) {
+				let file = self.file_cache.lock().unwrap().get_file_cache(&info);
+				match file {
 					Some(file) => {
 						match self.read_from_file(&file, offset as u64, buffer) {
 							Ok(size) => Ok(size as u64),
 							Err(error) => {
 								error!("An error occurred: {}", error);
 
-								Err(-1)
+								Err(SynologyError::Cache)
 							}
 						}
 					},
 					None => {
 						error!("File not found in the cache: {}", info.path);
 
-						Err(-1)
+						Err(SynologyError::Cache)
 					}
 				}
 			},
@@ -354,4 +1208,111 @@ impl FileStationFileSystem {
 
 		file.seek_read(buffer, offset)
 	}
-}
\ No newline at end of file
+}
+
+impl Drop for FileStationFileSystem {
+	fn drop(&mut self) {
+		// The last request to finish may let go of the file system on one of the runtime's own threads,
+		// where dropping the runtime would panic.
+		if let Some(runtime) = self.runtime.take() {
+			runtime.shutdown_background();
+		}
+	}
+}
+
+/// Where an entry whose real path is `real_path` leads, given the real paths of the shares, or `None`
+/// when it lives where `path` says.  Shares can be nested on the volume, as `home` is within `homes`,
+/// so an entry that fits its own share is never a link, and otherwise the innermost share wins.
+fn link_target(share_real_paths: &HashMap<String, String>, path: &str, real_path: &str) -> Option<String> {
+	let path = normalize_path(path);
+	let relative_to = |share_real_path: &str| real_path
+		.strip_prefix(share_real_path)
+		.filter(|rest| rest.is_empty() || rest.starts_with('/'));
+
+	let in_own_share = share_real_paths.iter().any(|(share_real_path, share_path)| {
+		relative_to(share_real_path).map_or(false, |rest| normalize_path(format!("{}{}", share_path, rest).as_str()) == path)
+	});
+	if in_own_share {
+		return None;
+	}
+
+	share_real_paths
+		.iter()
+		.filter_map(|(share_real_path, share_path)| relative_to(share_real_path).map(|rest| (share_real_path.len(), share_path, rest)))
+		.max_by_key(|(length, _, _)| *length)
+		.map(|(_, share_path, rest)| normalize_path(format!("{}{}", share_path, rest).as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// `home` is the logged-in user's folder within `homes`, as DSM sets them up.
+	fn nested_shares() -> HashMap<String, String> {
+		HashMap::from([
+			("/volume1/homes".to_string(), "/homes".to_string()),
+			("/volume1/homes/alice".to_string(), "/home".to_string()),
+			("/volume1/photo".to_string(), "/photo".to_string()),
+		])
+	}
+
+	#[test]
+	fn files_in_nested_shares_stay_regular() {
+		let shares = nested_shares();
+
+		assert_eq!(link_target(&shares, "/home/docs/report.pdf", "/volume1/homes/alice/docs/report.pdf"), None);
+		assert_eq!(link_target(&shares, "/homes/alice/docs/report.pdf", "/volume1/homes/alice/docs/report.pdf"), None);
+		assert_eq!(link_target(&shares, "/homes", "/volume1/homes"), None);
+	}
+
+	#[test]
+	fn links_lead_into_the_innermost_share() {
+		let shares = nested_shares();
+
+		assert_eq!(link_target(&shares, "/photo/latest", "/volume1/homes/alice/pictures"), Some("/home/pictures".to_string()));
+		assert_eq!(link_target(&shares, "/photo/everyone", "/volume1/homes/bob"), Some("/homes/bob".to_string()));
+	}
+
+	#[test]
+	fn links_outside_the_shares_are_not_resolved() {
+		assert_eq!(link_target(&nested_shares(), "/photo/backup", "/volume2/backup"), None);
+	}
+
+	#[test]
+	fn share_prefixes_only_match_whole_names() {
+		assert_eq!(link_target(&nested_shares(), "/photo/link", "/volume1/photos/2024"), None);
+	}
+
+	fn file(posix: u16) -> FileStationItem<FileAdditional> {
+		serde_json::from_value(serde_json::json!({
+			"isdir": false,
+			"name": "report.pdf",
+			"path": "/share/report.pdf",
+			"additional": {
+				"perm": { "posix": posix, "acl": { "append": false, "del": false, "exec": false, "read": true, "write": false } },
+				"size": 1024,
+				"time": { "atime": 1, "mtime": 2, "ctime": 3, "crtime": 4 }
+			}
+		})).unwrap()
+	}
+
+	#[test]
+	fn posix_digits_are_read_as_octal() {
+		assert_eq!(posix_mode(777), 0o777);
+		assert_eq!(posix_mode(755), 0o755);
+		assert_eq!(posix_mode(700), 0o700);
+		assert_eq!(posix_mode(644), 0o644);
+	}
+
+	#[test]
+	fn digits_that_are_not_octal_grant_nothing() {
+		assert_eq!(posix_mode(789), 0);
+	}
+
+	#[test]
+	fn files_carry_the_octal_mode() {
+		for (posix, mode) in [(777, 0o777), (755, 0o755), (700, 0o700)] {
+			assert_eq!(FileSystemInfo::from_file(&file(posix), 2).perm, mode);
+		}
+	}
+}
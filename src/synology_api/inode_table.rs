@@ -0,0 +1,229 @@
+use std::{path::Path, sync::Mutex};
+
+use log::error;
+use sqlite::{Connection, State};
+
+use super::SynologyError;
+
+/// How long a statement waits for other connections to the cache database to finish, in milliseconds.
+pub const BUSY_TIMEOUT: usize = 5000;
+
+/// Persistent mapping between paths on the NAS and inode numbers.
+///
+/// Inode numbers are allocated once per path and are never handed out again,
+/// so they stay stable across remounts.  The table itself is created by the
+/// `FileCache` database migrations.
+pub struct InodeTable {
+	connection: Mutex<Connection>,
+}
+
+impl InodeTable {
+	pub fn new(db_path: &Path) -> Result<InodeTable, SynologyError> {
+		match open_connection(db_path) {
+			Ok(connection) => Ok(InodeTable {
+				connection: Mutex::new(connection)
+			}),
+			Err(error) => {
+				error!("An error occurred while opening the inode table: {}", error);
+
+				Err(SynologyError::Cache)
+			}
+		}
+	}
+
+	pub fn get_path(&self, ino: u64) -> Result<Option<String>, SynologyError> {
+		let connection = self.connection.lock().unwrap();
+
+		match query_path(&connection, ino) {
+			Ok(path) => Ok(path),
+			Err(error) => {
+				error!("An error occurred while looking up the path for inode {}: {}", ino, error);
+
+				Err(SynologyError::Cache)
+			}
+		}
+	}
+
+	/// Returns the inode for `path`, allocating a new one if the path has never been seen.
+	pub fn insert(&self, path: &str) -> Result<u64, SynologyError> {
+		let connection = self.connection.lock().unwrap();
+
+		match insert_path(&connection, path) {
+			Ok(Some(ino)) => Ok(ino),
+			Ok(None) => {
+				error!("The inode for {} could not be allocated.", path);
+
+				Err(SynologyError::Cache)
+			},
+			Err(error) => {
+				error!("An error occurred while allocating an inode for {}: {}", path, error);
+
+				Err(SynologyError::Cache)
+			}
+		}
+	}
+
+	/// Returns the inodes for `paths`, in the same order, allocating new ones in a single transaction so a
+	/// large listing does not wait for a sync of the database per entry.
+	pub fn insert_all(&self, paths: &[String]) -> Result<Vec<u64>, SynologyError> {
+		let connection = self.connection.lock().unwrap();
+
+		match insert_paths(&connection, paths) {
+			Ok(inos) => Ok(inos),
+			Err(error) => {
+				error!("An error occurred while allocating inodes for {} paths: {}", paths.len(), error);
+
+				Err(SynologyError::Cache)
+			}
+		}
+	}
+
+	/// Moves `old_path` and everything below it to `new_path`, keeping their inode numbers.
+	pub fn rename(&self, old_path: &str, new_path: &str) -> Result<(), SynologyError> {
+		let connection = self.connection.lock().unwrap();
+
+		match rename_paths(&connection, old_path, new_path) {
+			Ok(()) => Ok(()),
+			Err(error) => {
+				error!("An error occurred while renaming {} to {}: {}", old_path, new_path, error);
+
+				Err(SynologyError::Cache)
+			}
+		}
+	}
+}
+
+/// Opens the cache database for a table that keeps its connection for the life of the mount.
+pub fn open_connection(db_path: &Path) -> Result<Connection, sqlite::Error> {
+	let mut connection = sqlite::open(db_path)?;
+	connection.set_busy_timeout(BUSY_TIMEOUT)?;
+
+	Ok(connection)
+}
+
+fn query_ino(connection: &Connection, path: &str) -> Result<Option<u64>, sqlite::Error> {
+	let mut statement = connection.prepare("SELECT ino FROM inodes WHERE path = ?")?;
+	statement.bind((1, path))?;
+
+	match statement.next()? {
+		State::Row => Ok(Some(statement.read::<i64, _>("ino")? as u64)),
+		State::Done => Ok(None)
+	}
+}
+
+fn query_path(connection: &Connection, ino: u64) -> Result<Option<String>, sqlite::Error> {
+	let mut statement = connection.prepare("SELECT path FROM inodes WHERE ino = ?")?;
+	statement.bind((1, ino as i64))?;
+
+	match statement.next()? {
+		State::Row => Ok(Some(statement.read::<String, _>("path")?)),
+		State::Done => Ok(None)
+	}
+}
+
+fn insert_path(connection: &Connection, path: &str) -> Result<Option<u64>, sqlite::Error> {
+	if let Some(ino) = query_ino(connection, path)? {
+		return Ok(Some(ino));
+	}
+
+	// AUTOINCREMENT guarantees that numbers are never reused, even for paths that were renamed away.
+	let mut statement = connection.prepare("INSERT OR IGNORE INTO inodes (path) VALUES (?)")?;
+	statement.bind((1, path))?;
+	statement.next()?;
+
+	query_ino(connection, path)
+}
+
+fn insert_paths(connection: &Connection, paths: &[String]) -> Result<Vec<u64>, sqlite::Error> {
+	connection.execute("BEGIN")?;
+
+	let mut inos: Vec<u64> = Vec::with_capacity(paths.len());
+	for path in paths {
+		match insert_path(connection, path) {
+			Ok(Some(ino)) => inos.push(ino),
+			result => {
+				if let Err(error) = connection.execute("ROLLBACK") {
+					error!("An error occurred while rolling back the inode allocation: {}", error);
+				}
+
+				return match result {
+					Err(error) => Err(error),
+					_ => Err(sqlite::Error {
+						code: None,
+						message: Some(format!("the inode for {} could not be allocated", path)),
+					})
+				};
+			}
+		}
+	}
+
+	connection.execute("COMMIT")?;
+
+	Ok(inos)
+}
+
+fn rename_paths(connection: &Connection, old_path: &str, new_path: &str) -> Result<(), sqlite::Error> {
+	// Whatever used to live at the destination is gone now.
+	let delete_query = "
+	DELETE FROM inodes
+	WHERE path = :path OR substr(path, 1, :length) = :prefix
+	";
+	let mut statement = connection.prepare(delete_query)?;
+	statement.bind((":path", new_path))?;
+	statement.bind((":length", (new_path.chars().count() + 1) as i64))?;
+	statement.bind((":prefix", format!("{}/", new_path).as_str()))?;
+	statement.next()?;
+
+	let update_query = "
+	UPDATE inodes
+	SET path = :new_path || substr(path, :start)
+	WHERE path = :old_path OR substr(path, 1, :length) = :prefix
+	";
+	let mut statement = connection.prepare(update_query)?;
+	statement.bind((":new_path", new_path))?;
+	statement.bind((":start", (old_path.chars().count() + 1) as i64))?;
+	statement.bind((":old_path", old_path))?;
+	statement.bind((":length", (old_path.chars().count() + 1) as i64))?;
+	statement.bind((":prefix", format!("{}/", old_path).as_str()))?;
+	statement.next()?;
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{env, fs, path::PathBuf, process};
+
+	use super::*;
+
+	fn table(name: &str) -> (InodeTable, PathBuf) {
+		let db_path = env::temp_dir().join(format!("inode-table-{}-{}.db", name, process::id()));
+		let _ = fs::remove_file(&db_path);
+
+		let connection = sqlite::open(&db_path).unwrap();
+		connection.execute("
+			CREATE TABLE inodes (ino INTEGER PRIMARY KEY AUTOINCREMENT, path TEXT NOT NULL UNIQUE);
+			INSERT INTO inodes (ino, path) VALUES (1, '/');
+		").unwrap();
+
+		(InodeTable::new(&db_path).unwrap(), db_path)
+	}
+
+	#[test]
+	fn insert_all_keeps_known_inodes_and_allocates_the_rest() {
+		let (table, db_path) = table("insert-all");
+		let known = table.insert("/home/b").unwrap();
+
+		let paths: Vec<String> = ["/home/a", "/home/b", "/home/c"].iter().map(|path| path.to_string()).collect();
+		let inos = table.insert_all(&paths).unwrap();
+
+		assert_eq!(inos.len(), 3);
+		assert_eq!(inos[1], known);
+		assert_ne!(inos[0], inos[2]);
+		assert_eq!(table.insert_all(&paths).unwrap(), inos);
+		assert_eq!(table.get_path(inos[2]).unwrap().as_deref(), Some("/home/c"));
+
+		drop(table);
+		fs::remove_file(db_path).unwrap();
+	}
+}
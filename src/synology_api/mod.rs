@@ -1,10 +1,23 @@
 pub use self::file_station_filesystem::{FileStationFileSystem, FileSystemInfo};
 pub use self::file_station::FileStation;
 pub use self::file_cache::FileCache;
-pub use self::utils::epoch_from_seconds;
+pub use self::error::SynologyError;
+pub use self::search::SearchQuery;
+pub use self::utils::{epoch_from_seconds, normalize_path};
 
+mod api_info;
+mod device_tokens;
+mod error;
 mod file_cache;
 mod file_station_filesystem;
 mod file_station;
+mod inode_table;
+mod request_batcher;
+mod response_cache;
+mod request;
 mod responses;
-mod utils;
\ No newline at end of file
+mod retry;
+mod search;
+mod tls;
+mod utils;
+mod worker_pool;
\ No newline at end of file
@@ -0,0 +1,194 @@
+use urlencoding::encode;
+
+use super::api_info::ApiEndpoint;
+
+/// A call to one of the APIs of the NAS.  Parameters are kept as given, and only encoded when
+/// the URL is built, so no caller has to get the escaping right.
+#[derive(Clone, Debug)]
+pub struct ApiRequest {
+    pub api: &'static str,
+    pub method: &'static str,
+    parameters: Vec<(&'static str, String)>,
+    /// Files the call is about, for invalidating what was cached about them.
+    paths: Vec<String>,
+}
+
+impl ApiRequest {
+    pub fn new(api: &'static str, method: &'static str) -> ApiRequest {
+        ApiRequest {
+            api,
+            method,
+            parameters: Vec::new(),
+            paths: Vec::new(),
+        }
+    }
+
+    pub fn param(mut self, name: &'static str, value: impl ToString) -> ApiRequest {
+        self.parameters.push((name, value.to_string()));
+        self
+    }
+
+    /// A list of values, sent as a JSON array.
+    pub fn list(self, name: &'static str, values: &[&str]) -> ApiRequest {
+        self.param(name, serde_json::to_string(values).unwrap())
+    }
+
+    /// A single file or folder.
+    pub fn path(mut self, name: &'static str, path: &str) -> ApiRequest {
+        self.paths.push(path.to_string());
+        self.param(name, path)
+    }
+
+    /// Several files or folders, sent as a JSON array since any of them may contain a comma.
+    pub fn paths(mut self, name: &'static str, paths: &[&str]) -> ApiRequest {
+        self.paths.extend(paths.iter().map(|path| path.to_string()));
+        self.list(name, paths)
+    }
+
+    pub fn get_paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// The encoded parameters, each preceded by `&`.
+    pub fn query(&self) -> String {
+        let mut query = String::new();
+        for (name, value) in self.parameters.iter() {
+            query += format!("&{}={}", name, encode(value)).as_ref();
+        }
+
+        query
+    }
+
+    /// The full URL of the call.  `sid` is left out for calls made before logging in.
+    pub fn url(&self, base_url: &str, endpoint: &ApiEndpoint, sid: Option<&str>) -> String {
+        let mut url = format!(
+            "{}/webapi/{}?api={}&version={}&method={}{}",
+            base_url,
+            endpoint.path,
+            encode(self.api),
+            endpoint.version,
+            encode(self.method),
+            self.query());
+
+        if let Some(sid) = sid {
+            url += format!("&_sid={}", encode(sid)).as_ref();
+        }
+
+        url
+    }
+
+    /// The same call with the same parameters gets the same key, whatever order they were given in.
+    pub fn cache_key(&self) -> String {
+        let mut parameters: Vec<String> = self.parameters
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, encode(value)))
+            .collect();
+        parameters.sort();
+
+        format!("{}.{}&{}", self.api, self.method, parameters.join("&"))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Names that break URLs, JSON or command lines when they are not escaped.
+    pub(crate) const HOSTILE_NAMES: [&str; 16] = [
+        "plain",
+        "with space",
+        "ünïcødé ファイル 😀",
+        "a&b=c",
+        "#fragment",
+        "100% done",
+        "%41 already encoded",
+        "a,b",
+        ",leading and trailing,",
+        "\"double quoted\"",
+        "'single quoted'",
+        "back\\slash",
+        "-leading-dash",
+        "--",
+        "semi;colon+plus?question",
+        "[\"looks\",\"like\",\"json\"]",
+    ];
+
+    pub(crate) fn endpoint() -> ApiEndpoint {
+        ApiEndpoint {
+            path: "entry.cgi".to_string(),
+            version: 2,
+        }
+    }
+
+    pub(crate) fn hostile_paths() -> Vec<String> {
+        HOSTILE_NAMES.iter().map(|name| format!("/share/{}/{}", name, name)).collect()
+    }
+
+    pub(crate) fn query_value(url: &str, name: &str) -> String {
+        let url = reqwest::Url::parse(url).unwrap();
+        let values: Vec<String> = url
+            .query_pairs()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+            .collect();
+
+        assert_eq!(values.len(), 1, "{} should appear once in {}", name, url);
+        values[0].clone()
+    }
+
+    #[test]
+    fn single_paths_survive_the_url() {
+        for path in hostile_paths() {
+            let request = ApiRequest::new("SYNO.FileStation.Download", "download")
+                .path("path", &path)
+                .param("mode", "download");
+            let url = request.url("https://nas:5001", &endpoint(), Some("sid"));
+
+            assert_eq!(query_value(&url, "path"), path);
+            assert_eq!(query_value(&url, "mode"), "download");
+            assert_eq!(query_value(&url, "_sid"), "sid");
+            assert!(!url.contains('#'), "{} was not escaped", path);
+        }
+    }
+
+    #[test]
+    fn path_lists_are_json_arrays() {
+        let paths = hostile_paths();
+        let path_refs: Vec<&str> = paths.iter().map(|path| path.as_str()).collect();
+
+        let request = ApiRequest::new("SYNO.FileStation.List", "getinfo").paths("path", &path_refs);
+        let url = request.url("https://nas:5001", &endpoint(), Some("sid"));
+
+        let decoded: Vec<String> = serde_json::from_str(&query_value(&url, "path")).unwrap();
+        assert_eq!(decoded, paths);
+        assert_eq!(request.get_paths(), paths.as_slice());
+    }
+
+    #[test]
+    fn encoded_values_contain_nothing_but_unreserved_characters_and_escapes() {
+        for name in HOSTILE_NAMES {
+            let query = ApiRequest::new("SYNO.FileStation.List", "list").param("name", name).query();
+            let value = query.strip_prefix("&name=").unwrap();
+
+            assert!(
+                value.chars().all(|c| c.is_ascii_alphanumeric() || "-._~%".contains(c)),
+                "{} was encoded as {}", name, value);
+        }
+    }
+
+    #[test]
+    fn commas_do_not_merge_paths() {
+        let one = ApiRequest::new("SYNO.FileStation.List", "getinfo").paths("path", &["/share/a,b"]);
+        let two = ApiRequest::new("SYNO.FileStation.List", "getinfo").paths("path", &["/share/a", "b"]);
+
+        assert_ne!(one.cache_key(), two.cache_key());
+    }
+
+    #[test]
+    fn cache_keys_ignore_parameter_order() {
+        let one = ApiRequest::new("SYNO.FileStation.List", "list").param("offset", 0).path("folder_path", "/share/a&b");
+        let two = ApiRequest::new("SYNO.FileStation.List", "list").path("folder_path", "/share/a&b").param("offset", 0);
+
+        assert_eq!(one.cache_key(), two.cache_key());
+    }
+}
@@ -0,0 +1,172 @@
+use std::{sync::{Arc, Condvar, Mutex}, thread, time::Duration};
+
+use super::SynologyError;
+
+/// Requests waiting for the same batch, in the order they arrived.
+type Batch<T> = Arc<Mutex<Vec<(String, Arc<Slot<T>>)>>>;
+
+/// Collects the requests several threads issue within a short window and answers them with a single call.
+///
+/// The first thread to arrive leads the batch: it waits for the window to pass, takes every request
+/// queued in the meantime, performs the call and hands each caller its own result.
+pub struct RequestBatcher<T> {
+	window: Duration,
+	max_batch_size: usize,
+	pending: Mutex<Option<Batch<T>>>,
+}
+
+struct Slot<T> {
+	result: Mutex<Option<Result<T, SynologyError>>>,
+	ready: Condvar,
+}
+
+impl<T> Slot<T> {
+	fn new() -> Slot<T> {
+		Slot {
+			result: Mutex::new(None),
+			ready: Condvar::new(),
+		}
+	}
+
+	fn fulfil(&self, result: Result<T, SynologyError>) {
+		*self.result.lock().unwrap() = Some(result);
+		self.ready.notify_one();
+	}
+
+	fn wait(&self) -> Result<T, SynologyError> {
+		let mut result = self.result.lock().unwrap();
+		loop {
+			if let Some(result) = result.take() {
+				return result;
+			}
+
+			result = self.ready.wait(result).unwrap();
+		}
+	}
+}
+
+/// The requests of a batch still waiting for their result.  Should the leader's call panic, the
+/// waiters are answered with an error as it unwinds, instead of waiting forever.
+struct Waiters<T> {
+	requests: Vec<(String, Arc<Slot<T>>)>,
+}
+
+impl<T> Drop for Waiters<T> {
+	fn drop(&mut self) {
+		for (_, waiting) in self.requests.drain(..) {
+			waiting.fulfil(Err(SynologyError::InvalidResponse));
+		}
+	}
+}
+
+impl<T> RequestBatcher<T> {
+	/// A zero `window` disables batching: every request is fetched on its own.
+	pub fn new(window: Duration, max_batch_size: usize) -> RequestBatcher<T> {
+		RequestBatcher {
+			window,
+			max_batch_size,
+			pending: Mutex::new(None),
+		}
+	}
+
+	/// Returns the result for `key`.  `fetch` is only called if this thread ends up leading the batch,
+	/// and must return one result per key, in the order of the keys.
+	pub fn request(&self, key: &str, fetch: impl FnOnce(&[String]) -> Vec<Result<T, SynologyError>>) -> Result<T, SynologyError> {
+		if self.window.is_zero() {
+			return fetch(&[key.to_string()]).into_iter().next().unwrap_or(Err(SynologyError::InvalidResponse));
+		}
+
+		let slot = Arc::new(Slot::new());
+		let batch = {
+			let mut pending = self.pending.lock().unwrap();
+
+			let joined = match pending.as_ref() {
+				Some(batch) => {
+					let mut requests = batch.lock().unwrap();
+					requests.push((key.to_string(), slot.clone()));
+
+					Some(requests.len() >= self.max_batch_size)
+				},
+				None => None
+			};
+
+			match joined {
+				Some(full) => {
+					// A full batch is closed, so the next request starts a new one.
+					if full {
+						*pending = None;
+					}
+
+					drop(pending);
+					return slot.wait();
+				},
+				None => {
+					let batch: Batch<T> = Arc::new(Mutex::new(vec!((key.to_string(), slot.clone()))));
+					*pending = Some(batch.clone());
+
+					batch
+				}
+			}
+		};
+
+		thread::sleep(self.window);
+
+		{
+			let mut pending = self.pending.lock().unwrap();
+			if pending.as_ref().map_or(false, |current| Arc::ptr_eq(current, &batch)) {
+				*pending = None;
+			}
+		}
+
+		let mut waiters = Waiters {
+			requests: std::mem::take(&mut *batch.lock().unwrap())
+		};
+		let keys: Vec<String> = waiters.requests.iter().map(|(key, _)| key.clone()).collect();
+
+		let mut results = fetch(&keys).into_iter();
+		for (_, waiting) in waiters.requests.drain(..) {
+			waiting.fulfil(results.next().unwrap_or(Err(SynologyError::InvalidResponse)));
+		}
+
+		slot.wait()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{sync::Arc, thread, time::Duration};
+
+	use super::RequestBatcher;
+
+	#[test]
+	fn waiters_get_an_error_when_the_leader_panics() {
+		let batcher: Arc<RequestBatcher<u64>> = Arc::new(RequestBatcher::new(Duration::from_millis(200), 10));
+
+		let leader = {
+			let batcher = batcher.clone();
+			thread::spawn(move || batcher.request("/a", |_| panic!("the call failed")))
+		};
+		thread::sleep(Duration::from_millis(50));
+
+		let result = batcher.request("/b", |keys| keys.iter().map(|_| Ok(1)).collect());
+
+		assert!(result.is_err());
+		assert!(leader.join().is_err());
+	}
+
+	#[test]
+	fn every_caller_gets_its_own_result() {
+		let batcher: Arc<RequestBatcher<usize>> = Arc::new(RequestBatcher::new(Duration::from_millis(200), 10));
+
+		let leader = {
+			let batcher = batcher.clone();
+			thread::spawn(move || batcher.request("/a", |keys| keys.iter().map(|key| Ok(key.len())).collect()))
+		};
+		thread::sleep(Duration::from_millis(50));
+
+		let result = batcher.request("/bb", |_| panic!("only the leader fetches"));
+
+		assert_eq!(result.ok(), Some(3));
+		assert_eq!(leader.join().unwrap().ok(), Some(2));
+	}
+}
@@ -0,0 +1,249 @@
+use std::{collections::{BTreeMap, HashMap}, sync::Mutex, time::{Duration, Instant}};
+
+use super::request::ApiRequest;
+
+/// How long responses stay fresh, for the calls worth caching.  Everything else always goes to the NAS.
+const TTLS: [(&str, &str, Duration); 3] = [
+    ("SYNO.FileStation.List", "list_share", Duration::from_secs(30)),
+    ("SYNO.FileStation.List", "list", Duration::from_secs(5)),
+    ("SYNO.FileStation.List", "getinfo", Duration::from_secs(5)),
+];
+
+/// Successful responses of read-only calls, so bursts of lookups do not all reach the NAS.
+///
+/// Entries are keyed by API, method and parameters, never by session, and the least recently used
+/// ones are dropped once the responses add up to more than the size limit.
+pub struct ResponseCache {
+    max_bytes: usize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    /// Keys by the time they were last used, oldest first.
+    usage: BTreeMap<u64, String>,
+    clock: u64,
+    bytes: usize,
+}
+
+struct CacheEntry {
+    value: serde_json::Value,
+    /// Files the response describes, either directly or as the entries of a folder.
+    paths: Vec<String>,
+    expires: Instant,
+    size: usize,
+    last_used: u64,
+}
+
+impl CacheState {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.usage.remove(&entry.last_used);
+            self.bytes -= entry.size;
+        }
+    }
+
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+        let now = self.clock;
+
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.usage.remove(&entry.last_used);
+            self.usage.insert(now, key.to_string());
+            entry.last_used = now;
+        }
+    }
+}
+
+impl ResponseCache {
+    pub fn new(max_bytes: usize) -> ResponseCache {
+        ResponseCache {
+            max_bytes,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<serde_json::Value> {
+        let mut state = self.state.lock().unwrap();
+
+        let expired = match state.entries.get(key) {
+            Some(entry) => entry.expires <= Instant::now(),
+            None => return None
+        };
+        if expired {
+            state.remove(key);
+            return None;
+        }
+
+        state.touch(key);
+        state.entries.get(key).map(|entry| entry.value.clone())
+    }
+
+    /// Keeps `value` if the call is worth caching.  Only successful responses should be given.
+    pub fn insert(&self, request: &ApiRequest, value: &serde_json::Value) {
+        let ttl = match TTLS.iter().find(|(api, method, _)| *api == request.api && *method == request.method) {
+            Some((_, _, ttl)) => *ttl,
+            None => return
+        };
+
+        let size = value.to_string().len();
+        if size > self.max_bytes {
+            return;
+        }
+
+        let key = request.cache_key();
+        let mut paths = request.get_paths().to_vec();
+        if paths.is_empty() {
+            // Calls without a path, such as the list of shares, describe the root.
+            paths.push("/".to_string());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.remove(&key);
+
+        while state.bytes + size > self.max_bytes {
+            let oldest = match state.usage.values().next() {
+                Some(oldest) => oldest.clone(),
+                None => break
+            };
+            state.remove(&oldest);
+        }
+
+        state.bytes += size;
+        state.entries.insert(key.clone(), CacheEntry {
+            value: value.clone(),
+            paths,
+            expires: Instant::now() + ttl,
+            size,
+            last_used: 0,
+        });
+        state.touch(&key);
+    }
+
+    /// Forgets whatever was cached about `path`, anything below it, and the folder holding it,
+    /// so a change made through the mount shows up right away.
+    pub fn invalidate_path(&self, path: &str) {
+        let path = path.trim_end_matches('/');
+        let parent = match path.rfind('/') {
+            Some(0) | None => "/",
+            Some(index) => &path[..index],
+        };
+        let prefix = format!("{}/", path);
+
+        let mut state = self.state.lock().unwrap();
+        let stale: Vec<String> = state.entries
+            .iter()
+            .filter(|(_, entry)| entry.paths.iter().any(|cached| cached == path || cached == parent || cached.starts_with(&prefix)))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in stale {
+            state.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn list(folder_path: &str) -> ApiRequest {
+        ApiRequest::new("SYNO.FileStation.List", "list").path("folder_path", folder_path)
+    }
+
+    fn getinfo(path: &str) -> ApiRequest {
+        ApiRequest::new("SYNO.FileStation.List", "getinfo").paths("path", &[path])
+    }
+
+    fn is_cached(cache: &ResponseCache, request: &ApiRequest) -> bool {
+        cache.get(&request.cache_key()).is_some()
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_response() {
+        let value = json!({ "files": [] });
+        let size = value.to_string().len();
+        let cache = ResponseCache::new(size * 2);
+
+        cache.insert(&list("/home/a"), &value);
+        cache.insert(&list("/home/b"), &value);
+        assert!(is_cached(&cache, &list("/home/a")));
+        cache.insert(&list("/home/c"), &value);
+
+        assert!(is_cached(&cache, &list("/home/a")));
+        assert!(!is_cached(&cache, &list("/home/b")));
+        assert!(is_cached(&cache, &list("/home/c")));
+    }
+
+    #[test]
+    fn skips_calls_that_are_not_worth_caching() {
+        let cache = ResponseCache::new(1 << 20);
+        let request = ApiRequest::new("SYNO.FileStation.CreateFolder", "create").path("folder_path", "/home");
+
+        cache.insert(&request, &json!({ "folders": [] }));
+
+        assert!(!is_cached(&cache, &request));
+    }
+
+    #[test]
+    fn expired_responses_are_dropped() {
+        let cache = ResponseCache::new(1 << 20);
+        let request = list("/home");
+        cache.insert(&request, &json!({ "files": [] }));
+        assert!(is_cached(&cache, &request));
+
+        cache.state.lock().unwrap().entries.get_mut(&request.cache_key()).unwrap().expires = Instant::now();
+
+        assert!(!is_cached(&cache, &request));
+        assert_eq!(cache.state.lock().unwrap().bytes, 0);
+    }
+
+    #[test]
+    fn creating_a_folder_invalidates_the_parent_listing() {
+        let cache = ResponseCache::new(1 << 20);
+        cache.insert(&list("/home/docs"), &json!({ "files": [] }));
+        cache.insert(&list("/home/photos"), &json!({ "files": [] }));
+
+        cache.invalidate_path("/home/docs/new folder");
+
+        assert!(!is_cached(&cache, &list("/home/docs")));
+        assert!(is_cached(&cache, &list("/home/photos")));
+    }
+
+    #[test]
+    fn renaming_invalidates_the_old_and_new_names_and_their_listings() {
+        let cache = ResponseCache::new(1 << 20);
+        cache.insert(&list("/home/docs"), &json!({ "files": [] }));
+        cache.insert(&list("/home/docs/old/deep"), &json!({ "files": [] }));
+        cache.insert(&getinfo("/home/docs/old"), &json!({ "files": [] }));
+        cache.insert(&getinfo("/home/docs/new"), &json!({ "files": [] }));
+        cache.insert(&getinfo("/home/docs/older"), &json!({ "files": [] }));
+
+        // What FileStation::rename invalidates.
+        cache.invalidate_path("/home/docs/old");
+        cache.invalidate_path("/home/docs/new");
+
+        assert!(!is_cached(&cache, &list("/home/docs")));
+        assert!(!is_cached(&cache, &list("/home/docs/old/deep")));
+        assert!(!is_cached(&cache, &getinfo("/home/docs/old")));
+        assert!(!is_cached(&cache, &getinfo("/home/docs/new")));
+        // A sibling that merely starts with the same name is left alone.
+        assert!(is_cached(&cache, &getinfo("/home/docs/older")));
+    }
+
+    #[test]
+    fn listings_of_the_shares_are_invalidated_by_changes_to_a_share() {
+        let cache = ResponseCache::new(1 << 20);
+        let list_shares = ApiRequest::new("SYNO.FileStation.List", "list_share");
+        cache.insert(&list_shares, &json!({ "shares": [] }));
+
+        cache.invalidate_path("/home");
+
+        assert!(!is_cached(&cache, &list_shares));
+    }
+}
@@ -10,7 +10,9 @@ pub struct SynologyResult<T> {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct LoginResult {
-    pub sid: String
+    pub sid: String,
+    #[serde(default)]
+    pub did: Option<String>
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -27,7 +29,11 @@ pub struct FileStationItem<T> {
 pub struct FileAdditional {
     pub perm: Perm,
     pub size: u64,
-    pub time: Time
+    pub time: Time,
+    #[serde(default)]
+    pub real_path: Option<String>,
+    #[serde(default)]
+    pub owner: Option<Owner>
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -35,7 +41,24 @@ pub struct FileAdditional {
 pub struct ShareAdditional {
     pub perm: Perm,
     pub time: Time,
-    pub volume_status: VolumeStatus
+    pub volume_status: VolumeStatus,
+    #[serde(default)]
+    pub real_path: Option<String>,
+    #[serde(default)]
+    pub owner: Option<Owner>
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct Owner {
+    #[serde(default)]
+    pub user: String,
+    #[serde(default)]
+    pub group: String,
+    #[serde(default)]
+    pub uid: u32,
+    #[serde(default)]
+    pub gid: u32
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -59,10 +82,12 @@ pub struct Time {
 #[serde(rename_all = "snake_case")]
 pub struct Perm {
     pub posix: u16,
+    #[serde(default)]
+    pub is_acl_mode: bool,
     pub acl: Acl
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "snake_case")]
 pub struct Acl {
     pub append: bool,
@@ -87,3 +112,40 @@ pub struct ListFilesResult {
     pub files: Vec<FileStationItem<FileAdditional>>,
     pub total: Option<i32>
 }
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct CreateFolderResult {
+    pub folders: Vec<FileStationItem<FileAdditional>>
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct BackgroundTaskResult {
+    pub taskid: String
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct Md5StatusResult {
+    pub finished: bool,
+    #[serde(default)]
+    pub md5: Option<String>
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct SearchListResult {
+    pub finished: bool,
+    #[serde(default)]
+    pub files: Vec<FileStationItem<FileAdditional>>,
+    pub total: Option<i32>
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiInfo {
+    pub path: String,
+    pub min_version: u32,
+    pub max_version: u32
+}
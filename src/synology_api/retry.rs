@@ -0,0 +1,65 @@
+use std::{collections::hash_map::RandomState, future::Future, hash::{BuildHasher, Hasher}, time::Duration};
+
+use log::warn;
+
+use super::SynologyError;
+
+/// Longest wait between two attempts, however many attempts failed before.
+const MAX_DELAY: Duration = Duration::from_secs(8);
+
+/// How often, and how patiently, requests that are safe to repeat are retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one.
+    attempts: u32,
+    /// Wait after the first failed attempt.  It doubles with every further failure.
+    base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(attempts: u32, base_delay: Duration) -> RetryPolicy {
+        RetryPolicy {
+            attempts: attempts.max(1),
+            base_delay,
+        }
+    }
+
+    /// A policy that gives up after the first attempt, for requests that must not be repeated.
+    pub fn never() -> RetryPolicy {
+        RetryPolicy::new(1, Duration::ZERO)
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error retrying cannot fix, or runs out of attempts.
+    pub async fn run<T, F, Fut>(&self, description: &str, mut operation: F) -> Result<T, SynologyError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, SynologyError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Err(error) if error.is_transient() && attempt < self.attempts => {
+                    let delay = self.delay(attempt);
+                    warn!("{} failed ({}), retrying in {:?}. Attempt {} of {}.", description, error, delay, attempt + 1, self.attempts);
+
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                },
+                result => return result
+            }
+        }
+    }
+
+    /// Exponential backoff with jitter, so clients that failed together do not all come back at once.
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.base_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(MAX_DELAY);
+
+        // Each RandomState is seeded randomly, which is all the randomness jitter needs.
+        let random = RandomState::new().build_hasher().finish();
+        let jitter = (random % 1000) as f64 / 1000.0;
+
+        backoff.mul_f64(0.5 + jitter / 2.0)
+    }
+}
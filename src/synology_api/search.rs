@@ -0,0 +1,177 @@
+/// What to look for on the NAS.  Every filter is optional, and a file has to pass all of those given.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// Folders searched, including their subfolders.  Every share when empty.
+    pub folders: Vec<String>,
+    /// Case-insensitive glob the names have to match, e.g. `*report*`.
+    pub pattern: Option<String>,
+    /// Extension the files have to have, e.g. `pdf`.  Folders never match once it is given.
+    pub extension: Option<String>,
+    /// Smallest size in bytes.
+    pub size_from: Option<u64>,
+    /// Largest size in bytes.
+    pub size_to: Option<u64>,
+    /// Earliest modification time, in seconds since the epoch.
+    pub modified_after: Option<u64>,
+    /// Latest modification time, in seconds since the epoch.
+    pub modified_before: Option<u64>,
+}
+
+impl SearchQuery {
+    /// Reads the query from command line arguments: `--name=value` filters, and the folders to search.
+    /// Arguments that are not filters, such as mount options, are left alone.
+    pub fn from_args(args: &[String]) -> Result<SearchQuery, String> {
+        let mut query = SearchQuery::default();
+
+        for arg in args {
+            match arg.strip_prefix("--") {
+                Some(option) => {
+                    if let Some((name, value)) = option.split_once('=') {
+                        query.set(name, value)?;
+                    }
+                },
+                None => query.folders.push(arg.clone())
+            }
+        }
+
+        Ok(query)
+    }
+
+    /// Reads the query from the name of a search folder: filters joined with `&`, such as
+    /// `extension=pdf&size-from=1M`.  Other names are refused, so looking up `.git` or `desktop.ini`
+    /// in the search folder does not search the whole NAS.
+    pub fn from_name(name: &str) -> Result<SearchQuery, String> {
+        let mut query = SearchQuery::default();
+
+        for filter in name.split('&') {
+            match filter.split_once('=') {
+                Some((name, value)) => {
+                    if !query.set(name, value)? {
+                        return Err(format!("unknown filter {}", name));
+                    }
+                },
+                None => return Err(format!("{} is not a filter", filter))
+            }
+        }
+
+        Ok(query)
+    }
+
+    /// Applies one filter.  Returns whether `name` is a filter at all.
+    fn set(&mut self, name: &str, value: &str) -> Result<bool, String> {
+        match name {
+            "pattern" => self.pattern = Some(value.to_string()),
+            "extension" => self.extension = Some(value.trim_start_matches('.').to_string()),
+            "size-from" => self.size_from = Some(parse_size(value).ok_or(format!("{} is not a size", value))?),
+            "size-to" => self.size_to = Some(parse_size(value).ok_or(format!("{} is not a size", value))?),
+            "modified-after" => self.modified_after = Some(parse_time(value).ok_or(format!("{} is not a time", value))?),
+            "modified-before" => self.modified_before = Some(parse_time(value).ok_or(format!("{} is not a time", value))?),
+            _ => return Ok(false)
+        }
+
+        Ok(true)
+    }
+}
+
+/// Bytes, optionally with a `K`, `M` or `G` suffix for kibibytes, mebibytes or gibibytes.
+fn parse_size(value: &str) -> Option<u64> {
+    let (number, unit) = match value.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((index, _)) => value.split_at(index),
+        None => (value, "")
+    };
+
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return None
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Seconds since the epoch, or a `YYYY-MM-DD` date taken as midnight UTC.
+fn parse_time(value: &str) -> Option<u64> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(seconds);
+    }
+
+    let parts: Vec<&str> = value.split('-').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+
+    let year = parts[0].parse::<i64>().ok()?;
+    let month = parts[1].parse::<i64>().ok().filter(|month| (1..=12).contains(month))?;
+    let day = parts[2].parse::<i64>().ok().filter(|day| (1..=31).contains(day))?;
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar, counting years from March
+    // so the leap day comes last.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    u64::try_from(days * 86400).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_with_and_without_units() {
+        assert_eq!(parse_size("0"), Some(0));
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("512B"), Some(512));
+        assert_eq!(parse_size("1K"), Some(1024));
+        assert_eq!(parse_size("1m"), Some(1048576));
+        assert_eq!(parse_size("10M"), Some(10485760));
+        assert_eq!(parse_size("2G"), Some(2147483648));
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("1T"), None);
+        assert_eq!(parse_size("1.5M"), None);
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size("99999999999999999999G"), None);
+    }
+
+    #[test]
+    fn times_as_seconds_or_dates() {
+        assert_eq!(parse_time("1700000000"), Some(1700000000));
+        assert_eq!(parse_time("1970-01-01"), Some(0));
+        assert_eq!(parse_time("2000-02-29"), Some(951782400));
+        assert_eq!(parse_time("2000-03-01"), Some(951868800));
+        assert_eq!(parse_time("2024-03-01"), Some(1709251200));
+        assert_eq!(parse_time("2024-12-31"), Some(1735603200));
+    }
+
+    #[test]
+    fn invalid_times() {
+        assert_eq!(parse_time(""), None);
+        assert_eq!(parse_time("2024-13-01"), None);
+        assert_eq!(parse_time("2024-00-10"), None);
+        assert_eq!(parse_time("2024-01-32"), None);
+        assert_eq!(parse_time("2024-01"), None);
+        assert_eq!(parse_time("1969-12-31"), None);
+        assert_eq!(parse_time("yesterday"), None);
+    }
+
+    #[test]
+    fn search_names_need_filters() {
+        let query = SearchQuery::from_name("extension=pdf&size-from=1M").unwrap();
+        assert_eq!(query.extension.as_deref(), Some("pdf"));
+        assert_eq!(query.size_from, Some(1048576));
+
+        assert!(SearchQuery::from_name("*.pdf").is_err());
+        assert!(SearchQuery::from_name(".git").is_err());
+        assert!(SearchQuery::from_name("colour=blue").is_err());
+    }
+}
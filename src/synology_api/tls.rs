@@ -0,0 +1,98 @@
+use std::{fs, path::Path};
+
+use log::{error, info, warn};
+use reqwest::{Certificate, ClientBuilder, Response, tls::TlsInfo};
+use sha2::{Digest, Sha256};
+
+use crate::options::Options;
+
+use super::SynologyError;
+
+/// Makes `builder` trust the certificates the options ask for, on top of or instead of the system ones.
+pub fn configure_tls(mut builder: ClientBuilder, hostname: &str, options: &Options) -> Result<ClientBuilder, SynologyError> {
+    if let Some(fingerprint) = &options.fingerprint {
+        if options.ca_bundle.is_some() {
+            warn!("The certificate of {} is pinned, so the CA bundle is not used.", hostname);
+        }
+
+        // The certificate of every connection is checked against the fingerprint once it is established,
+        // rather than against any authority.  DSM's certificates are issued by a CA it generates itself,
+        // and rarely name the NAS the way it is reached, which does not matter as long as nobody else
+        // holds the key.
+        info!("Only the certificate with the SHA-256 fingerprint {} is trusted for {}.", fingerprint, hostname);
+        builder = builder
+            .danger_accept_invalid_certs(true)
+            .tls_info(true);
+    } else if let Some(path) = &options.ca_bundle {
+        for certificate in load_ca_bundle(path)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    if options.accept_invalid_certificates {
+        warn!("Any certificate is accepted from {}. The connection is encrypted, but whoever sits in between can read and change it.", hostname);
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    Ok(builder)
+}
+
+/// Reads every certificate of a PEM file.
+fn load_ca_bundle(path: &Path) -> Result<Vec<Certificate>, SynologyError> {
+    let bundle = match fs::read_to_string(path) {
+        Ok(bundle) => bundle,
+        Err(error) => {
+            error!("Could not read the CA bundle {}: {}", path.display(), error);
+
+            return Err(SynologyError::UntrustedCertificate);
+        }
+    };
+
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    let mut certificates = Vec::new();
+    for block in bundle.split(BEGIN).skip(1) {
+        match Certificate::from_pem(format!("{}{}", BEGIN, block).as_bytes()) {
+            Ok(certificate) => certificates.push(certificate),
+            Err(error) => {
+                error!("Could not parse a certificate of the CA bundle {}: {}", path.display(), error);
+
+                return Err(SynologyError::UntrustedCertificate);
+            }
+        }
+    }
+
+    if certificates.is_empty() {
+        error!("The CA bundle {} does not contain any certificate.", path.display());
+
+        return Err(SynologyError::UntrustedCertificate);
+    }
+
+    info!("Trusting {} certificates from {}.", certificates.len(), path.display());
+    Ok(certificates)
+}
+
+/// Checks that the connection `response` came over is to a peer presenting the certificate with the
+/// SHA-256 fingerprint `fingerprint`.  Requires the client to be built with `tls_info`.
+pub fn check_pinned_certificate(response: &Response, fingerprint: &str) -> Result<(), SynologyError> {
+    let certificate = match response.extensions().get::<TlsInfo>().and_then(|tls_info| tls_info.peer_certificate()) {
+        Some(certificate) => certificate,
+        None => {
+            error!("{} did not present a certificate.", response.url().host_str().unwrap_or_default());
+
+            return Err(SynologyError::UntrustedCertificate);
+        }
+    };
+
+    let actual: String = Sha256::digest(certificate).iter().map(|byte| format!("{:02x}", byte)).collect();
+    if actual != fingerprint {
+        error!(
+            "The certificate of {} has the SHA-256 fingerprint {}, not the pinned {}.",
+            response.url().host_str().unwrap_or_default(),
+            actual,
+            fingerprint);
+
+        return Err(SynologyError::UntrustedCertificate);
+    }
+
+    Ok(())
+}
@@ -2,4 +2,11 @@ use std::time::{SystemTime, Duration};
 
 pub fn epoch_from_seconds(seconds: u64) -> SystemTime {
 	SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+/// Collapses duplicate separators and strips the trailing one, so each file has exactly one path.
+pub fn normalize_path(path: &str) -> String {
+	let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+
+	format!("/{}", parts.join("/"))
 }
\ No newline at end of file
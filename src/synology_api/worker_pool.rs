@@ -0,0 +1,111 @@
+use std::{sync::{Arc, Mutex, mpsc::{self, Receiver, Sender}}, thread};
+
+use log::error;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of threads running the jobs handed to them, in the order they arrived.
+///
+/// Jobs wait for a free thread rather than each starting one of their own.  Threads are started as
+/// jobs come in, up to the limit, and end once the pool is dropped and they finished what they ran.
+pub struct WorkerPool {
+	name: &'static str,
+	max_threads: usize,
+	sender: Mutex<Sender<Job>>,
+	receiver: Arc<Mutex<Receiver<Job>>>,
+	threads: Mutex<usize>,
+}
+
+impl WorkerPool {
+	pub fn new(name: &'static str, max_threads: usize) -> WorkerPool {
+		let (sender, receiver) = mpsc::channel();
+
+		WorkerPool {
+			name,
+			max_threads,
+			sender: Mutex::new(sender),
+			receiver: Arc::new(Mutex::new(receiver)),
+			threads: Mutex::new(0),
+		}
+	}
+
+	pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+		if let Err(error) = self.sender.lock().unwrap().send(Box::new(job)) {
+			error!("The {} pool is shut down: {}", self.name, error);
+			return;
+		}
+
+		let mut threads = self.threads.lock().unwrap();
+		if *threads < self.max_threads {
+			let receiver = self.receiver.clone();
+			let spawned = thread::Builder::new()
+				.name(format!("{}-{}", self.name, *threads))
+				.spawn(move || Self::work(receiver));
+
+			match spawned {
+				Ok(_) => *threads += 1,
+				Err(error) => error!("Could not start another {} thread: {}", self.name, error)
+			}
+		}
+	}
+
+	fn work(receiver: Arc<Mutex<Receiver<Job>>>) {
+		loop {
+			// The lock is only held while waiting, so the other threads take the next jobs.
+			let job = match receiver.lock().unwrap().recv() {
+				Ok(job) => job,
+				Err(_) => break
+			};
+
+			job();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{sync::{Arc, atomic::{AtomicUsize, Ordering}, mpsc}, thread, time::Duration};
+
+	use super::WorkerPool;
+
+	#[test]
+	fn runs_every_job() {
+		let pool = WorkerPool::new("test", 4);
+		let (sender, receiver) = mpsc::channel();
+
+		for job in 0..32 {
+			let sender = sender.clone();
+			pool.execute(move || sender.send(job).unwrap());
+		}
+
+		let mut done: Vec<i32> = receiver.iter().take(32).collect();
+		done.sort();
+		assert_eq!(done, (0..32).collect::<Vec<i32>>());
+	}
+
+	#[test]
+	fn never_runs_more_jobs_than_threads_at_once() {
+		let pool = WorkerPool::new("test", 2);
+		let running = Arc::new(AtomicUsize::new(0));
+		let most_running = Arc::new(AtomicUsize::new(0));
+		let (sender, receiver) = mpsc::channel();
+
+		for _ in 0..8 {
+			let running = running.clone();
+			let most_running = most_running.clone();
+			let sender = sender.clone();
+
+			pool.execute(move || {
+				let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+				most_running.fetch_max(now_running, Ordering::SeqCst);
+				thread::sleep(Duration::from_millis(20));
+				running.fetch_sub(1, Ordering::SeqCst);
+
+				sender.send(()).unwrap();
+			});
+		}
+
+		assert_eq!(receiver.iter().take(8).count(), 8);
+		assert_eq!(most_running.load(Ordering::SeqCst), 2);
+	}
+}